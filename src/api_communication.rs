@@ -1,21 +1,228 @@
 //! This module contains functions for communicating with the Site24x7 and Zoho APIs.
 use anyhow::{anyhow, Context, Result};
-use log::{debug, info};
+use chrono::{DateTime, Utc};
+use log::{debug, info, warn};
+use reqwest::StatusCode;
+use tokio::sync::{Semaphore, SemaphorePermit};
 
-use crate::parsing::parse_current_status;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::parsing::parse_current_status_page;
 use crate::{site24x7_types, zoho_types};
 
+/// Version of the Site24x7 API whose response format we understand.
+pub const DEFAULT_API_VERSION: &str = "2.0";
+
+/// How many pages of current status we'll fetch at most in case the API never stops telling us
+/// that there are more.
+const MAX_PAGES: u32 = 1000;
+
+/// Settings for retrying failed upstream requests and for limiting how many are made at once.
+#[derive(Clone, Debug, Default)]
+pub struct RetryPolicy {
+    /// How often a failed request may be retried before giving up.
+    pub max_retries: u32,
+    /// Overall time a request may take including all of its retries.
+    pub timeout: Option<Duration>,
+    /// Permits for the requests that may be in flight at the same time (unlimited if `None`).
+    ///
+    /// Clones of the policy share the permits.
+    pub concurrency_limit: Option<Arc<Semaphore>>,
+}
+
+impl RetryPolicy {
+    /// Wait until we may make another request if the number of concurrent requests is limited.
+    ///
+    /// Waiting is bound by the timeout of the policy. The error is then built using `timeout`.
+    async fn acquire_permit<E>(
+        &self,
+        timeout: fn(String) -> E,
+    ) -> Result<Option<SemaphorePermit<'_>>, E> {
+        let Some(semaphore) = &self.concurrency_limit else {
+            return Ok(None);
+        };
+        let permit = match self.timeout {
+            Some(duration) => tokio::time::timeout(duration, semaphore.acquire())
+                .await
+                .map_err(|_| {
+                    timeout(format!(
+                        "Waited longer than {duration:?} for other requests to finish"
+                    ))
+                })?,
+            None => semaphore.acquire().await,
+        };
+        Ok(Some(permit.expect("Semaphore is never closed")))
+    }
+}
+
+/// How long to wait before the first retry.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Upper bound for the time to wait between retries.
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// How long to wait before making retry number `retry` (starting at 1).
+///
+/// The wait time doubles with every retry so that we don't hammer an upstream that is already
+/// struggling.
+pub fn backoff(retry: u32) -> Duration {
+    let factor = 2u32
+        .checked_pow(retry.saturating_sub(1))
+        .unwrap_or(u32::MAX);
+    INITIAL_BACKOFF
+        .checked_mul(factor)
+        .map_or(MAX_BACKOFF, |backoff| backoff.min(MAX_BACKOFF))
+}
+
+/// The kind of upstream request being made.
+///
+/// This matters for retries as not every request is safe to be repeated blindly.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RequestKind {
+    /// The POST to Zoho which exchanges the refresh token for a new access token.
+    AccessToken,
+    /// The GET of the current monitor status from Site24x7.
+    CurrentStatus,
+}
+
+/// Why a single request attempt failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FailureKind {
+    /// We couldn't establish a connection so the server never saw the request.
+    Connect,
+    /// The request was sent but we didn't get a response for it.
+    Request,
+    /// The server responded with an unsuccessful status code.
+    Status(StatusCode),
+}
+
+impl From<&reqwest::Error> for FailureKind {
+    fn from(e: &reqwest::Error) -> Self {
+        if e.is_connect() {
+            FailureKind::Connect
+        } else {
+            FailureKind::Request
+        }
+    }
+}
+
+/// Decide whether a request of `kind` that failed with `failure` may be retried.
+///
+/// Fetching the current status is a read-only GET so it can be retried whenever the failure
+/// might be transient, which is the case for network errors and 5xx responses.
+///
+/// Requesting an access token is a POST that mints a new token on the Zoho side. Zoho limits how
+/// many tokens may be created in a given timeframe so we don't want to repeat a request that
+/// might have already succeeded on the server side. A 5xx or a lost response doesn't tell us
+/// whether that was the case. Hence, we only retry if the request never made it to the server.
+pub fn should_retry(kind: RequestKind, failure: FailureKind) -> bool {
+    match (kind, failure) {
+        (_, FailureKind::Connect) => true,
+        (RequestKind::AccessToken, _) => false,
+        (RequestKind::CurrentStatus, FailureKind::Request) => true,
+        (RequestKind::CurrentStatus, FailureKind::Status(status)) => status.is_server_error(),
+    }
+}
+
+/// Send `request`, retrying it according to `retry_policy` if that is safe for `kind`.
+///
+/// All attempts together are bound by the timeout of `retry_policy`. Each attempt only gets the
+/// time that is left and we don't retry if the backoff alone would exceed it.
+///
+/// Responses with an unsuccessful status are returned as-is once we're out of retries (or if we
+/// may not retry) so that the caller can make sense of the body.
+async fn send_with_retries(
+    kind: RequestKind,
+    retry_policy: &RetryPolicy,
+    request: reqwest::RequestBuilder,
+) -> reqwest::Result<reqwest::Response> {
+    let deadline = retry_policy.timeout.map(|timeout| Instant::now() + timeout);
+    let mut attempt = 0;
+    loop {
+        let mut attempt_request = request
+            .try_clone()
+            .expect("Requests to the API never have streaming bodies");
+        if let Some(deadline) = deadline {
+            attempt_request =
+                attempt_request.timeout(deadline.saturating_duration_since(Instant::now()));
+        }
+        let (failure, result) = match attempt_request.send().await {
+            Ok(resp) if resp.status().is_success() => return Ok(resp),
+            Ok(resp) => (FailureKind::Status(resp.status()), Ok(resp)),
+            Err(e) => (FailureKind::from(&e), Err(e)),
+        };
+        if attempt >= retry_policy.max_retries || !should_retry(kind, failure) {
+            return result;
+        }
+        attempt += 1;
+        let delay = backoff(attempt);
+        if deadline.is_some_and(|deadline| Instant::now() + delay >= deadline) {
+            warn!(
+                "{:?} request failed ({:?}), no time left to retry",
+                kind, failure
+            );
+            return result;
+        }
+        warn!(
+            "{:?} request failed ({:?}), retrying in {:?} ({}/{})",
+            kind, failure, delay, attempt, retry_policy.max_retries
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// Read how long to wait from a `Retry-After` header.
+fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    let value = headers.get(reqwest::header::RETRY_AFTER)?.to_str().ok()?;
+    parse_retry_after(value, Utc::now())
+}
+
+/// Parse the value of a `Retry-After` header relative to `now`.
+///
+/// The value is either a number of seconds or an HTTP date. A date in the past means we don't
+/// have to wait at all.
+fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (date.with_timezone(&Utc) - now)
+            .to_std()
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+/// Convert a failed request into the error type `E` of the caller.
+///
+/// Timeouts are kept apart from other errors using the `timeout` variant so that they can be told
+/// apart further up.
+fn request_error<E: From<anyhow::Error>>(
+    e: reqwest::Error,
+    timeout: fn(String) -> E,
+    context: &'static str,
+) -> E {
+    if e.is_timeout() {
+        timeout(e.to_string())
+    } else {
+        anyhow::Error::from(e).context(context).into()
+    }
+}
+
 /// Acquire the access token.
 ///
 /// An access token is a short-lived token that can be used to query the
 /// API multiple times. It will become invalidated after a short period of
-/// time.
+/// time which is why it is returned along with its lifetime.
 /// See https://www.site24x7.com/help/api/index.html#authentication
 pub async fn get_access_token(
     client: &reqwest::Client,
     site24x7_client_info: &site24x7_types::Site24x7ClientInfo,
     refresh_token: &str,
-) -> Result<String> {
+    retry_policy: &RetryPolicy,
+) -> Result<zoho_types::AccessToken, zoho_types::AccessTokenError> {
     let access_token_request = zoho_types::AccessTokenRequest {
         client_id: site24x7_client_info.client_id.clone(),
         client_secret: site24x7_client_info.client_secret.clone(),
@@ -23,19 +230,38 @@ pub async fn get_access_token(
         grant_type: "refresh_token".into(),
     };
 
+    let _permit = retry_policy
+        .acquire_permit(zoho_types::AccessTokenError::ApiTimeoutError)
+        .await?;
     let access_token_endpoint = format!("{}/oauth/v2/token", &site24x7_client_info.zoho_endpoint);
     info!("Requesting access token from {}", access_token_endpoint);
     debug!(
         "Getting access token with info:\n{:#?}",
         access_token_request
     );
-    let access_token_resp = client
-        .post(&access_token_endpoint)
-        .form(&access_token_request)
-        .send()
-        .await?;
+    let access_token_resp = send_with_retries(
+        RequestKind::AccessToken,
+        retry_policy,
+        client
+            .post(&access_token_endpoint)
+            .form(&access_token_request),
+    )
+    .await
+    .map_err(|e| {
+        request_error(
+            e,
+            zoho_types::AccessTokenError::ApiTimeoutError,
+            "Error during web request to get access token.",
+        )
+    })?;
 
-    let access_token_resp_text = access_token_resp.text().await?;
+    let access_token_resp_text = access_token_resp.text().await.map_err(|e| {
+        request_error(
+            e,
+            zoho_types::AccessTokenError::ApiTimeoutError,
+            "Couldn't stream text from response",
+        )
+    })?;
 
     let access_token_resp_parsed =
         serde_json::from_str(&access_token_resp_text).context(format!(
@@ -45,11 +271,110 @@ pub async fn get_access_token(
         zoho_types::AccessTokenResponse::Success(inner) => {
             info!("Successfully acquired access token");
             debug!("Access token value: {}", inner.access_token);
-            Ok(inner.access_token)
+            info!(
+                "Access token was issued by {} and expires in {}s",
+                inner.api_domain, inner.expires_in
+            );
+            Ok(zoho_types::AccessToken {
+                access_token: inner.access_token,
+                expires_in: Duration::from_secs(inner.expires_in.into()),
+                api_domain: inner.api_domain,
+            })
         }
         zoho_types::AccessTokenResponse::Error(e) => Err(anyhow!(
             "Error while getting access token. Server replied '{}'",
             e.error
+        )
+        .into()),
+    }
+}
+
+/// Receive the raw JSON of all monitor statuses as sent by Site24x7.
+///
+/// The response is in the format of `api_version` (see [`DEFAULT_API_VERSION`]). Responses with an
+/// unsuccessful status are turned into errors.
+///
+/// Only the first page is returned if the data is paginated.
+pub async fn fetch_current_status_text(
+    client: &reqwest::Client,
+    site24x7_endpoint: &str,
+    api_version: &str,
+    access_token: &str,
+    retry_policy: &RetryPolicy,
+) -> Result<String, site24x7_types::CurrentStatusError> {
+    fetch_current_status_page_text(
+        client,
+        site24x7_endpoint,
+        api_version,
+        access_token,
+        retry_policy,
+        None,
+    )
+    .await
+}
+
+/// Receive the raw JSON of the given `page` of monitor statuses or the first one if it's `None`.
+async fn fetch_current_status_page_text(
+    client: &reqwest::Client,
+    site24x7_endpoint: &str,
+    api_version: &str,
+    access_token: &str,
+    retry_policy: &RetryPolicy,
+    page: Option<u32>,
+) -> Result<String, site24x7_types::CurrentStatusError> {
+    let _permit = retry_policy
+        .acquire_permit(site24x7_types::CurrentStatusError::ApiTimeoutError)
+        .await?;
+    let mut request = client.get(format!("{site24x7_endpoint}/current_status"));
+    if let Some(page) = page {
+        request = request.query(&[("page", page)]);
+    }
+    let current_status_resp = send_with_retries(
+        RequestKind::CurrentStatus,
+        retry_policy,
+        request
+            .header("Accept", format!("application/json; version={api_version}"))
+            .header("Authorization", format!("Zoho-oauthtoken {access_token}")),
+    )
+    .await
+    .map_err(|e| {
+        request_error(
+            e,
+            site24x7_types::CurrentStatusError::ApiTimeoutError,
+            "Error during web request to fetch curent status.",
+        )
+    })?;
+
+    let status = current_status_resp.status();
+    let retry_after = retry_after(current_status_resp.headers());
+    let current_status_resp_text = current_status_resp.text().await.map_err(|e| {
+        request_error(
+            e,
+            site24x7_types::CurrentStatusError::ApiTimeoutError,
+            "Couldn't stream text from response",
+        )
+    })?;
+
+    // Error bodies don't necessarily have the shape we expect so we'll rather look at the status
+    // first. They might also be entire HTML pages so we only log them when debugging.
+    if !status.is_success() {
+        debug!(
+            "Unsuccessful response from server ({status}): \n{}",
+            current_status_resp_text
+        );
+    }
+    match status {
+        status if status.is_success() => Ok(current_status_resp_text),
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+            Err(site24x7_types::CurrentStatusError::ApiAuthError(format!(
+                "{status}: {current_status_resp_text}"
+            )))
+        }
+        StatusCode::TOO_MANY_REQUESTS => {
+            Err(site24x7_types::CurrentStatusError::RateLimited(retry_after))
+        }
+        _ => Err(site24x7_types::CurrentStatusError::ApiUnknownError(
+            status.to_string(),
         )),
     }
 }
@@ -57,23 +382,256 @@ pub async fn get_access_token(
 /// Receive an update for all monitor statuses.
 ///
 /// Given a valid `access_token`, this will try to get a new set of fresh monitor data.
+/// If Site24x7 splits it into several pages, all of them are fetched and combined.
 pub async fn fetch_current_status(
     client: &reqwest::Client,
     site24x7_endpoint: &str,
+    api_version: &str,
     access_token: &str,
+    retry_policy: &RetryPolicy,
 ) -> Result<site24x7_types::CurrentStatusData, site24x7_types::CurrentStatusError> {
-    let current_status_resp = client
-        .get(format!("{site24x7_endpoint}/current_status"))
-        .header("Accept", "application/json; version=2.0")
-        .header("Authorization", format!("Zoho-oauthtoken {access_token}"))
-        .send()
-        .await
-        .context("Error during web request to fetch curent status.")?;
+    let mut current_status_data = site24x7_types::CurrentStatusData {
+        monitors: vec![],
+        monitor_groups: vec![],
+    };
+    let mut page = None;
+    loop {
+        let current_status_resp_text = fetch_current_status_page_text(
+            client,
+            site24x7_endpoint,
+            api_version,
+            access_token,
+            retry_policy,
+            page,
+        )
+        .await?;
+        let (data, page_info) = parse_current_status_page(&current_status_resp_text)?;
+        current_status_data.monitors.extend(data.monitors);
+        current_status_data
+            .monitor_groups
+            .extend(data.monitor_groups);
+        match page_info {
+            Some(page_info) if page_info.has_next => {
+                if page_info.page >= MAX_PAGES {
+                    return Err(site24x7_types::CurrentStatusError::ApiUnknownError(
+                        format!("Current status has more than {MAX_PAGES} pages"),
+                    ));
+                }
+                debug!("Fetching page {} of current status", page_info.page + 1);
+                page = Some(page_info.page + 1);
+            }
+            _ => return Ok(current_status_data),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Response, Server};
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
-    let current_status_resp_text = current_status_resp
-        .text()
+    use super::*;
+
+    #[tokio::test]
+    /// With a limit of 1, requests should be made one after another instead of all at once.
+    async fn concurrency_limit_serializes_requests() {
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+        let (in_flight_seen, max_in_flight_seen) = (in_flight.clone(), max_in_flight.clone());
+        let make_service = make_service_fn(move |_conn| {
+            let (in_flight, max_in_flight) = (in_flight.clone(), max_in_flight.clone());
+            async move {
+                Ok::<_, hyper::Error>(service_fn(move |_req| {
+                    let (in_flight, max_in_flight) = (in_flight.clone(), max_in_flight.clone());
+                    async move {
+                        let now_in_flight = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                        max_in_flight.fetch_max(now_in_flight, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        in_flight.fetch_sub(1, Ordering::SeqCst);
+                        Ok::<_, hyper::Error>(Response::new(Body::from("{}")))
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let endpoint = format!("http://{}/api", server.local_addr());
+        tokio::spawn(server);
+
+        let client = reqwest::Client::new();
+        let retry_policy = RetryPolicy {
+            concurrency_limit: Some(Arc::new(Semaphore::new(1))),
+            ..Default::default()
+        };
+        let fetch = || {
+            fetch_current_status_text(
+                &client,
+                &endpoint,
+                DEFAULT_API_VERSION,
+                "token",
+                &retry_policy,
+            )
+        };
+        let (first, second, third) = tokio::join!(fetch(), fetch(), fetch());
+        assert!(first.is_ok() && second.is_ok() && third.is_ok());
+        assert_eq!(max_in_flight_seen.load(Ordering::SeqCst), 1);
+        assert_eq!(in_flight_seen.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    /// The requested API version should be sent along in the `Accept` header.
+    async fn api_version_is_requested() {
+        let accept_seen = Arc::new(std::sync::Mutex::new(vec![]));
+        let accept = accept_seen.clone();
+        let make_service = make_service_fn(move |_conn| {
+            let accept = accept.clone();
+            async move {
+                Ok::<_, hyper::Error>(service_fn(move |req: hyper::Request<Body>| {
+                    accept.lock().unwrap().push(
+                        req.headers()[hyper::header::ACCEPT]
+                            .to_str()
+                            .unwrap()
+                            .to_string(),
+                    );
+                    async { Ok::<_, hyper::Error>(Response::new(Body::from("{}"))) }
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let endpoint = format!("http://{}/api", server.local_addr());
+        tokio::spawn(server);
+
+        let client = reqwest::Client::new();
+        let retry_policy = RetryPolicy::default();
+        for api_version in [DEFAULT_API_VERSION, "3.0"] {
+            fetch_current_status_text(&client, &endpoint, api_version, "token", &retry_policy)
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            *accept_seen.lock().unwrap(),
+            [
+                "application/json; version=2.0",
+                "application/json; version=3.0"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    /// All pages of a paginated current status should be fetched and combined.
+    async fn paginated_current_status() {
+        let pages_seen = Arc::new(std::sync::Mutex::new(vec![]));
+        let pages = pages_seen.clone();
+        let make_service = make_service_fn(move |_conn| {
+            let pages = pages.clone();
+            async move {
+                Ok::<_, hyper::Error>(service_fn(move |req: hyper::Request<Body>| {
+                    let query = req.uri().query().map(str::to_string);
+                    let body = match query.as_deref() {
+                        None => include_str!("../tests/data/paginated_page_1.json"),
+                        Some("page=2") => include_str!("../tests/data/paginated_page_2.json"),
+                        Some(_) => "{}",
+                    };
+                    pages.lock().unwrap().push(query);
+                    async move { Ok::<_, hyper::Error>(Response::new(Body::from(body))) }
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let endpoint = format!("http://{}/api", server.local_addr());
+        tokio::spawn(server);
+
+        let client = reqwest::Client::new();
+        let data = fetch_current_status(
+            &client,
+            &endpoint,
+            DEFAULT_API_VERSION,
+            "token",
+            &RetryPolicy::default(),
+        )
         .await
-        .context("Couldn't stream text from response")?;
+        .unwrap();
+        let names: Vec<_> = data
+            .monitors
+            .iter()
+            .map(|m| m.monitor().unwrap().name.as_str())
+            .collect();
+        assert_eq!(names, ["test1", "test2"]);
+        assert_eq!(
+            *pages_seen.lock().unwrap(),
+            [None, Some("page=2".to_string())]
+        );
+    }
+
+    #[test]
+    /// `Retry-After` may either be given in seconds or as an HTTP date.
+    fn retry_after_formats() {
+        let now = DateTime::parse_from_rfc3339("2015-10-21T07:28:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(
+            parse_retry_after("120", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(parse_retry_after(" 0 ", now), Some(Duration::ZERO));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:30:00 GMT", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+        assert_eq!(parse_retry_after("-5", now), None);
+    }
 
-    parse_current_status(&current_status_resp_text)
+    #[test]
+    /// The wait between retries should double every time but never grow unbounded.
+    fn backoff_grows_exponentially_up_to_a_limit() {
+        assert_eq!(backoff(1), Duration::from_millis(500));
+        assert_eq!(backoff(2), Duration::from_secs(1));
+        assert_eq!(backoff(3), Duration::from_secs(2));
+        assert_eq!(backoff(4), Duration::from_secs(4));
+        assert_eq!(backoff(5), Duration::from_secs(8));
+        assert_eq!(backoff(6), MAX_BACKOFF);
+        assert_eq!(backoff(u32::MAX), MAX_BACKOFF);
+    }
+
+    #[test]
+    /// Status requests are read-only and may be retried on any transient failure.
+    fn current_status_retries_transient_failures() {
+        let kind = RequestKind::CurrentStatus;
+        assert!(should_retry(kind, FailureKind::Connect));
+        assert!(should_retry(kind, FailureKind::Request));
+        assert!(should_retry(
+            kind,
+            FailureKind::Status(StatusCode::SERVICE_UNAVAILABLE)
+        ));
+        assert!(!should_retry(
+            kind,
+            FailureKind::Status(StatusCode::UNAUTHORIZED)
+        ));
+        assert!(!should_retry(
+            kind,
+            FailureKind::Status(StatusCode::NOT_FOUND)
+        ));
+    }
+
+    #[test]
+    /// Token requests might have succeeded server-side unless we never connected.
+    fn access_token_only_retries_connect_failures() {
+        let kind = RequestKind::AccessToken;
+        assert!(should_retry(kind, FailureKind::Connect));
+        assert!(!should_retry(kind, FailureKind::Request));
+        assert!(!should_retry(
+            kind,
+            FailureKind::Status(StatusCode::INTERNAL_SERVER_ERROR)
+        ));
+        assert!(!should_retry(
+            kind,
+            FailureKind::Status(StatusCode::BAD_REQUEST)
+        ));
+    }
 }
@@ -1,6 +1,59 @@
 //! Geolocation data related to the Site24x7 locations.
+use std::io::Write;
+
+use anyhow::{Context, Result};
+use flate2::{write::GzEncoder, Compression, Crc};
+use hyper::body::Bytes;
 use serde::Serialize;
 
+/// The geolocation info serialized to JSON and GeoJSON.
+///
+/// The geolocation info never changes while we're running so we only need to do this once.
+#[derive(Clone, Debug)]
+pub struct SerializedGeoLocationInfo {
+    pub json: SerializedDocument,
+    pub geojson: SerializedDocument,
+}
+
+impl SerializedGeoLocationInfo {
+    pub fn new(geolocation_info: &[GeoLocationInfo]) -> Result<Self> {
+        let json = serde_json::to_vec_pretty(geolocation_info)
+            .context("Couldn't serialize geolocation info")?;
+        let geojson = serde_json::to_vec_pretty(&FeatureCollection::from(geolocation_info))
+            .context("Couldn't serialize geolocation info as GeoJSON")?;
+        Ok(SerializedGeoLocationInfo {
+            json: SerializedDocument::new(json)?,
+            geojson: SerializedDocument::new(geojson)?,
+        })
+    }
+}
+
+/// A serialized document, both plain and gzip-compressed, along with an `ETag` identifying it.
+#[derive(Clone, Debug)]
+pub struct SerializedDocument {
+    pub plain: Bytes,
+    pub gzip: Bytes,
+    pub etag: String,
+}
+
+impl SerializedDocument {
+    fn new(plain: Vec<u8>) -> Result<Self> {
+        let mut crc = Crc::new();
+        crc.update(&plain);
+        let etag = format!("\"{:08x}\"", crc.sum());
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder
+            .write_all(&plain)
+            .and_then(|_| encoder.finish())
+            .map(|gzip| SerializedDocument {
+                plain: plain.into(),
+                gzip: gzip.into(),
+                etag,
+            })
+            .context("Couldn't compress geolocation info")
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct GeoLocationInfo {
     pub key: &'static str,
@@ -9,6 +62,49 @@ pub struct GeoLocationInfo {
     pub name: &'static str,
 }
 
+/// A GeoJSON `FeatureCollection` as per RFC 7946 with a `Point` feature for every location.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type")]
+pub struct FeatureCollection {
+    pub features: Vec<Feature>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type")]
+pub struct Feature {
+    pub geometry: Point,
+    pub properties: FeatureProperties,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type")]
+pub struct Point {
+    /// Longitude and latitude, in that order.
+    pub coordinates: [f64; 2],
+}
+
+#[derive(Debug, Serialize)]
+pub struct FeatureProperties {
+    pub name: &'static str,
+}
+
+impl From<&[GeoLocationInfo]> for FeatureCollection {
+    fn from(geolocation_info: &[GeoLocationInfo]) -> Self {
+        let features = geolocation_info
+            .iter()
+            .map(|location| Feature {
+                geometry: Point {
+                    coordinates: [location.longitude, location.latitude],
+                },
+                properties: FeatureProperties {
+                    name: location.name,
+                },
+            })
+            .collect();
+        FeatureCollection { features }
+    }
+}
+
 /// Initialize a big static list of gep
 pub fn get_geolocation_info() -> Vec<GeoLocationInfo> {
     vec![
@@ -218,3 +314,35 @@ pub fn get_geolocation_info() -> Vec<GeoLocationInfo> {
         },
     ]
 }
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    /// Every location should become a GeoJSON point with its coordinates in longitude, latitude
+    /// order.
+    fn geojson_feature_collection() {
+        let geolocation_info = get_geolocation_info();
+        let feature_collection = FeatureCollection::from(&geolocation_info[..]);
+        assert_eq!(feature_collection.features.len(), geolocation_info.len());
+        let geojson = serde_json::to_value(&feature_collection).unwrap();
+        assert_eq!(geojson["type"], "FeatureCollection");
+        assert_eq!(
+            geojson["features"][0],
+            json!({
+                "type": "Feature",
+                "geometry": {
+                    "type": "Point",
+                    "coordinates": [4.88969, 52.37403],
+                },
+                "properties": {
+                    "name": "Amsterdam - NL",
+                },
+            })
+        );
+    }
+}
@@ -0,0 +1,171 @@
+//! Module containing an encoder for the InfluxDB line protocol.
+//!
+//! Every sample becomes a line with the metric name as its measurement and the labels as its tags,
+//! much like Telegraf does it when scraping Prometheus metrics.
+//! See https://docs.influxdata.com/influxdb/v2/reference/syntax/line-protocol/
+use std::io::Write;
+
+use prometheus::proto::{Metric, MetricFamily, MetricType};
+use prometheus::Encoder;
+
+/// Content type of the InfluxDB line protocol.
+pub const LINE_PROTOCOL_FORMAT: &str = "text/plain; charset=utf-8";
+
+/// Encoder for the InfluxDB line protocol.
+#[derive(Debug)]
+pub struct LineProtocolEncoder {
+    /// Timestamp of all lines in nanoseconds since the epoch.
+    timestamp_ns: i64,
+}
+
+impl LineProtocolEncoder {
+    pub fn new(timestamp_ns: i64) -> Self {
+        LineProtocolEncoder { timestamp_ns }
+    }
+}
+
+impl Encoder for LineProtocolEncoder {
+    fn encode<W: Write>(
+        &self,
+        metric_families: &[MetricFamily],
+        writer: &mut W,
+    ) -> prometheus::Result<()> {
+        for mf in metric_families {
+            for m in mf.get_metric() {
+                let fields = match mf.get_field_type() {
+                    MetricType::COUNTER => vec![("value".to_string(), m.get_counter().get_value())],
+                    MetricType::GAUGE => vec![("value".to_string(), m.get_gauge().get_value())],
+                    // We never create untyped metrics but we'll still need to handle them.
+                    #[allow(deprecated)]
+                    MetricType::UNTYPED => vec![("value".to_string(), m.get_untyped().get_value())],
+                    MetricType::HISTOGRAM => {
+                        let h = m.get_histogram();
+                        let mut fields = vec![
+                            ("count".to_string(), h.get_sample_count() as f64),
+                            ("sum".to_string(), h.get_sample_sum()),
+                        ];
+                        fields.extend(h.get_bucket().iter().map(|b| {
+                            (
+                                b.get_upper_bound().to_string(),
+                                b.get_cumulative_count() as f64,
+                            )
+                        }));
+                        fields
+                    }
+                    MetricType::SUMMARY => {
+                        let s = m.get_summary();
+                        let mut fields = vec![
+                            ("count".to_string(), s.get_sample_count() as f64),
+                            ("sum".to_string(), s.get_sample_sum()),
+                        ];
+                        fields.extend(
+                            s.get_quantile()
+                                .iter()
+                                .map(|q| (q.get_quantile().to_string(), q.get_value())),
+                        );
+                        fields
+                    }
+                };
+                self.write_line(writer, mf.get_name(), m, &fields)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn format_type(&self) -> &str {
+        LINE_PROTOCOL_FORMAT
+    }
+}
+
+impl LineProtocolEncoder {
+    /// Write a single line for `metric` with the given `fields`.
+    ///
+    /// The line protocol can neither represent NaN nor infinite values so such fields are left
+    /// out and so is the whole line if no field remains.
+    fn write_line<W: Write>(
+        &self,
+        writer: &mut W,
+        measurement: &str,
+        metric: &Metric,
+        fields: &[(String, f64)],
+    ) -> prometheus::Result<()> {
+        let fields: Vec<_> = fields
+            .iter()
+            .filter(|(_, value)| value.is_finite())
+            .map(|(key, value)| format!("{}={value}", escape(key, ",= ")))
+            .collect();
+        if fields.is_empty() {
+            return Ok(());
+        }
+        write!(writer, "{}", escape(measurement, ", "))?;
+        // Tags can't be empty so we leave them out just like a missing label.
+        for label in metric.get_label() {
+            if !label.get_value().is_empty() {
+                write!(
+                    writer,
+                    ",{}={}",
+                    escape(label.get_name(), ",= "),
+                    escape(label.get_value(), ",= ")
+                )?;
+            }
+        }
+        writeln!(writer, " {} {}", fields.join(","), self.timestamp_ns)?;
+        Ok(())
+    }
+}
+
+/// Escape `special` characters in `s` with a backslash.
+fn escape(s: &str, special: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if c == '\\' || special.contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use prometheus::{GaugeVec, HistogramOpts, HistogramVec, IntCounter, Opts, Registry};
+
+    use super::*;
+
+    #[test]
+    /// Labels should become escaped tags, empty ones should be left out and values that can't be
+    /// represented should be skipped.
+    fn gauges_counters_and_histograms() -> prometheus::Result<()> {
+        let registry = Registry::new();
+        let counter = IntCounter::new("requests_total", "Number of requests.")?;
+        counter.inc();
+        registry.register(Box::new(counter))?;
+        let gauge = GaugeVec::new(
+            Opts::new("latency_seconds", "Latency."),
+            &["location", "monitor_group"],
+        )?;
+        gauge.with_label_values(&["London - UK", ""]).set(0.421);
+        gauge
+            .with_label_values(&["Bucharest, RO", "a=b"])
+            .set(f64::INFINITY);
+        registry.register(Box::new(gauge))?;
+        let histogram = HistogramVec::new(
+            HistogramOpts::new("latency_seconds_histogram", "Latency.").buckets(vec![0.5, 1.0]),
+            &["monitor_type"],
+        )?;
+        histogram.with_label_values(&["URL"]).observe(0.75);
+        registry.register(Box::new(histogram))?;
+
+        let mut buffer = vec![];
+        LineProtocolEncoder::new(1609958586000000000).encode(&registry.gather(), &mut buffer)?;
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            r#"latency_seconds,location=London\ -\ UK value=0.421 1609958586000000000
+latency_seconds_histogram,monitor_type=URL count=1,sum=0.75,0.5=0,1=1 1609958586000000000
+requests_total value=1 1609958586000000000
+"#
+        );
+        Ok(())
+    }
+}
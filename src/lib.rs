@@ -0,0 +1,51 @@
+//! A Prometheus compatible exporter for [site24x7.com](https://www.site24x7.com/).
+//!
+//! Besides the `site24x7_exporter` binary, this crate can be used as a library to talk to the
+//! Site24x7 API and to make sense of its responses:
+//!
+//! ```
+//! use site24x7_exporter::parsing::parse_current_status;
+//! use site24x7_exporter::site24x7_types::MonitorMaybe;
+//!
+//! let current_status = r#"{
+//!     "data": {
+//!         "monitors": [{
+//!             "monitor_type": "URL",
+//!             "name": "example",
+//!             "monitor_id": "01",
+//!             "attributeName": "RESPONSETIME",
+//!             "status": 1,
+//!             "locations": [{"location_name": "London - UK", "attribute_value": 421, "status": 1}]
+//!         }]
+//!     }
+//! }"#;
+//! let data = parse_current_status(current_status).unwrap();
+//! let MonitorMaybe::URL(monitor) = &data.monitors[0] else {
+//!     panic!("Expected a URL monitor");
+//! };
+//! assert_eq!(monitor.name, "example");
+//! assert_eq!(monitor.locations[0].attribute_value, Some(421.0));
+//! ```
+
+pub mod api_communication;
+pub mod parsing;
+pub mod site24x7_types;
+pub mod zoho_types;
+
+// These modules make up the exporter itself and are only public for the sake of the binary.
+#[doc(hidden)]
+pub mod args;
+#[doc(hidden)]
+pub mod geodata;
+#[doc(hidden)]
+pub mod influx;
+#[doc(hidden)]
+pub mod logging;
+#[doc(hidden)]
+pub mod metrics;
+#[doc(hidden)]
+pub mod openmetrics;
+#[doc(hidden)]
+pub mod streaming;
+#[doc(hidden)]
+pub mod web_service;
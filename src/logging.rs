@@ -0,0 +1,55 @@
+//! Module containing the setup of logging.
+use anyhow::Result;
+use chrono::{SecondsFormat, Utc};
+use log::{LevelFilter, Log, Metadata, Record};
+use simplelog::TermLogger;
+
+use crate::args::LogFormat;
+
+/// Logger writing one JSON object per line to stdout so that log shippers don't have to parse
+/// free-form text.
+struct JsonLogger {
+    level: LevelFilter,
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = serde_json::json!({
+            "timestamp": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            "level": record.level().as_str(),
+            "message": record.args().to_string(),
+            "module": record.module_path(),
+        });
+        println!("{line}");
+    }
+
+    fn flush(&self) {}
+}
+
+/// Set up the global logger to log messages of at least `level` in the given `format`.
+///
+/// Text messages of at least `thread_level` are annotated with the thread they're logged from.
+pub fn init(level: LevelFilter, format: &LogFormat, thread_level: LevelFilter) -> Result<()> {
+    match format {
+        LogFormat::Text => TermLogger::init(
+            level,
+            simplelog::ConfigBuilder::new()
+                .set_thread_level(thread_level)
+                .build(),
+            simplelog::TerminalMode::Mixed,
+            simplelog::ColorChoice::Auto,
+        )?,
+        LogFormat::Json => {
+            log::set_boxed_logger(Box::new(JsonLogger { level }))?;
+            log::set_max_level(level);
+        }
+    }
+    Ok(())
+}
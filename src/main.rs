@@ -1,142 +1,328 @@
-use anyhow::{Context, Result};
-use clap::{crate_name, crate_version, Parser};
+use anyhow::{bail, Context, Result};
+use clap::{crate_name, crate_version};
+use hyper::server::accept::Accept;
+use hyper::server::conn::AddrIncoming;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::Server;
-use lazy_static::lazy_static;
 use log::{debug, info};
-use prometheus::{GaugeVec, IntGaugeVec};
-use simplelog::TermLogger;
-use tokio::sync::RwLock;
-
-use std::sync::Arc;
-
-mod api_communication;
-mod args;
-mod geodata;
-mod metrics;
-mod parsing;
-mod site24x7_types;
-mod web_service;
-mod zoho_types;
-
-lazy_static! {
-    pub static ref CLIENT: reqwest::Client = reqwest::Client::new();
-    pub static ref MONITOR_UP_GAUGE: IntGaugeVec = prometheus::register_int_gauge_vec!(
-        "site24x7_monitor_up",
-        "Current health status of the monitor (1 = UP, 0 = DOWN).",
-        &["monitor_type", "monitor_name", "monitor_group", "location"]
-    )
-    .expect("Couldn't create monitor_up metric");
-    pub static ref MONITOR_LATENCY_SECONDS_GAUGE: GaugeVec = prometheus::register_gauge_vec!(
-        "site24x7_monitor_latency_seconds",
-        "Last measured latency in seconds.",
-        &["monitor_type", "monitor_name", "monitor_group", "location"]
-    )
-    .expect("Couldn't create monitor_latency_seconds metric");
-}
+use tokio::sync::{watch, RwLock};
+use tokio::task::JoinSet;
+
+use std::error::Error as StdError;
+use std::sync::atomic::{AtomicBool, AtomicU64};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use site24x7_exporter::{
+    api_communication, args, geodata, logging, metrics, site24x7_types, web_service,
+};
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let args = args::Config::parse();
+    let started_at = Instant::now();
+    let args = args::Config::load()?;
+    args.validate()?;
 
-    TermLogger::init(
-        args.loglevel,
-        simplelog::ConfigBuilder::new()
-            .set_thread_level(simplelog::LevelFilter::Trace)
-            .build(),
-        simplelog::TerminalMode::Mixed,
-        simplelog::ColorChoice::Auto,
-    )?;
+    logging::init(args.loglevel, &args.log_format, args.log_thread_level)?;
 
-    dotenv::dotenv().ok();
+    match &args.env_file {
+        Some(env_file) => dotenv::from_path(env_file)
+            .with_context(|| format!("Couldn't load env file {}", env_file.display()))?,
+        None => {
+            dotenv::dotenv().ok();
+        }
+    }
 
     info!("{} {}", crate_name!(), crate_version!());
+    let metrics = metrics::Metrics::new(&metrics::MetricsSettings {
+        namespace: args.metrics_namespace.clone(),
+        latency_buckets: args.latency_buckets.clone(),
+        include_group_id: args.include_group_id,
+        include_unit_label: args.include_unit_label,
+        include_account: !args.accounts.is_empty(),
+    })
+    .context("Couldn't create metrics")?;
+    metrics::set_build_info(&metrics);
+
+    let site24x7_endpoint = args.site24x7_endpoint_url();
+    let zoho_endpoint = args.zoho_accounts_url();
+    info!("Using site24x7 endpoint: {}", site24x7_endpoint);
+    info!("Using Zoho endpoint: {}", zoho_endpoint);
+    let client_info =
+        |client_id: String, client_secret: String| site24x7_types::Site24x7ClientInfo {
+            site24x7_endpoint: site24x7_endpoint.clone(),
+            api_version: args.api_version.clone(),
+            zoho_endpoint: zoho_endpoint.clone(),
+            client_id,
+            client_secret,
+        };
+
+    // We resolve the proxies ourselves instead of letting reqwest pick them up so that we know
+    // which ones are in use.
+    let proxies = args.proxies(|var| std::env::var(var).ok())?;
+    info!("{}", proxies);
+    let mut client_builder = reqwest::Client::builder()
+        .timeout(args.timeout)
+        .user_agent(&args.user_agent)
+        .pool_idle_timeout(args.pool_idle_timeout)
+        .tcp_keepalive(args.tcp_keepalive.map(Duration::from));
+    if let Some(pool_max_idle_per_host) = args.pool_max_idle_per_host {
+        client_builder = client_builder.pool_max_idle_per_host(pool_max_idle_per_host);
+    }
+    if let Some(identity) = args.client_identity()? {
+        info!("Authenticating to upstream APIs with a TLS client certificate");
+        client_builder = client_builder.identity(identity);
+    }
+    let client = proxies
+        .apply(client_builder)?
+        .build()
+        .context("Couldn't build HTTP client")?;
 
-    let client_id = std::env::var("ZOHO_CLIENT_ID").context("ZOHO_CLIENT_ID must be set")?;
-    let client_secret =
-        std::env::var("ZOHO_CLIENT_SECRET").context("ZOHO_CLIENT_SECRET must be set")?;
-    let refresh_token =
-        std::env::var("ZOHO_REFRESH_TOKEN").context("ZOHO_REFRESH_TOKEN must be set")?;
-
-    let site24x7_client_info = site24x7_types::Site24x7ClientInfo {
-        site24x7_endpoint: format!("https://www.{}/api", args.site24x7_endpoint),
-        zoho_endpoint: format!(
-            "https://accounts.zoho.{}",
-            args.site24x7_endpoint
-                .to_string()
-                .splitn(2, '.')
-                .last()
-                .unwrap()
-        ),
-        client_id,
-        client_secret,
+    debug!("Reqwest client:\n{:#?}", client);
+
+    let retry_policy = api_communication::RetryPolicy {
+        max_retries: args.max_retries,
+        timeout: Some(args.timeout),
+        concurrency_limit: (args.max_concurrent > 0)
+            .then(|| Arc::new(tokio::sync::Semaphore::new(args.max_concurrent))),
     };
 
-    // Figure out Zoho accounts endpoint.
-    info!(
-        "Using site24x7 endpoint: {}",
-        site24x7_client_info.site24x7_endpoint
-    );
-    info!(
-        "Using Zoho endpoint: {}",
-        site24x7_client_info.zoho_endpoint
-    );
-
-    // Info print used proxies if there are any.
-    // Currently we have to do this in a stupid backwards way by parsing the debug output.
-    // Hopefully, we'll be able to do this properly once this is fixed:
-    // https://github.com/seanmonstar/reqwest/issues/967
-    let debug_output = format!("{:?}", *CLIENT);
-    let re = regex::Regex::new(r"^.*System\(\{(.*?)\}").unwrap();
-    if let Some(caps) = re.captures(&debug_output) {
-        if let Some(cap) = caps.get(1) {
-            if cap.as_str().is_empty() {
-                info!("Not using any proxies");
+    let mut accounts = vec![];
+    if args.accounts.is_empty() {
+        // A directly provided access token saves us from having to set up the refresh flow but it
+        // can't be renewed once it expires.
+        let direct_access_token =
+            args::read_optional_secret(args.access_token_file.as_deref(), "ZOHO_ACCESS_TOKEN")?;
+        let (client_id, client_secret, refresh_token) = if direct_access_token.is_some() {
+            info!("Using the provided access token which won't be renewed");
+            (String::new(), String::new(), None)
+        } else {
+            let [client_id, client_secret, refresh_token] = args::read_required_secrets([
+                (args.client_id_file.as_deref(), "ZOHO_CLIENT_ID"),
+                (args.client_secret_file.as_deref(), "ZOHO_CLIENT_SECRET"),
+                (args.refresh_token_file.as_deref(), "ZOHO_REFRESH_TOKEN"),
+            ])?;
+            (client_id, client_secret, Some(refresh_token))
+        };
+        accounts.push(
+            connect_account(
+                &client,
+                &metrics,
+                &retry_policy,
+                String::new(),
+                client_info(client_id, client_secret),
+                refresh_token,
+                direct_access_token,
+            )
+            .await?,
+        );
+    } else {
+        info!("Fetching data of {} accounts", args.accounts.len());
+        for account in &args.accounts {
+            let account = connect_account(
+                &client,
+                &metrics,
+                &retry_policy,
+                account.name.clone(),
+                client_info(account.client_id.clone(), account.client_secret.clone()),
+                Some(account.refresh_token.clone()),
+                None,
+            )
+            .await
+            .with_context(|| format!("Couldn't set up account {}", account.name))?;
+            accounts.push(account);
+        }
+    }
+
+    if args.check {
+        for account in &accounts {
+            let access_token = account.access_token.read().await;
+            let current_status_data = api_communication::fetch_current_status(
+                &client,
+                &account.site24x7_client_info.site24x7_endpoint,
+                &account.site24x7_client_info.api_version,
+                &access_token,
+                &retry_policy,
+            )
+            .await
+            .context("Couldn't fetch the current status")?;
+            let found = metrics::describe_current_status(&current_status_data);
+            if account.name.is_empty() {
+                println!("Check passed: found {found}");
             } else {
-                info!("Picked up proxies: {}", &caps[1]);
+                println!("Check passed for account {}: found {found}", account.name);
             }
         }
+        return Ok(());
     }
 
-    debug!("Reqwest client:\n{:#?}", *CLIENT);
+    let mut metrics_options = metrics::MetricsOptions {
+        monitor_include_regex: args.monitor_include_regex,
+        monitor_exclude_regex: args.monitor_exclude_regex,
+        group_include: args.group_include.into_iter().collect(),
+        group_exclude: args.group_exclude.into_iter().collect(),
+        nan_on_non_up: args.nan_on_non_up,
+        use_poll_timestamp: args.use_poll_timestamp,
+        staleness_threshold: (!args.staleness_threshold.is_zero())
+            .then_some(args.staleness_threshold),
+        ..Default::default()
+    };
+    for (group_name, monitor_type) in &args.group_expected_types {
+        metrics_options
+            .group_expected_types
+            .entry(group_name.clone())
+            .or_default()
+            .insert(monitor_type.clone());
+    }
+
+    let state = Arc::new(web_service::AppState {
+        client,
+        accounts,
+        metrics_path: args.metrics_path.to_string(),
+        geolocation_path: if args.disable_geolocation {
+            None
+        } else {
+            Some(args.geolocation_path.to_string())
+        },
+        disable_landing_page: args.disable_landing_page,
+        debug_path: args.debug_path.map(|p| p.to_string()),
+        influx_path: args.influx_path.map(|p| p.to_string()),
+        cors_allow_origin: args.cors_allow_origin,
+        max_request_body_size: args.max_request_body_size,
+        metrics,
+        metrics_options,
+        retry_policy,
+        ready: AtomicBool::new(false),
+        geolocation_info: geodata::SerializedGeoLocationInfo::new(&geodata::get_geolocation_info())?,
+        cache_ttl: args.cache_ttl,
+        cached_current_status: RwLock::new(None),
+        last_current_status: Mutex::new(vec![]),
+        refresh_lock: tokio::sync::Mutex::new(()),
+        refreshes: AtomicU64::new(0),
+        started_at,
+        exit_after_requests: args.exit_after_requests,
+        scrapes_served: AtomicU64::new(0),
+        shutdown: watch::Sender::new(false),
+    });
 
+    if let Some(unix_socket) = &args.unix_socket {
+        return serve_unix_socket(unix_socket, state).await;
+    }
+
+    // Bind to all addresses before serving any of them so that we don't run half-way.
+    let mut incomings = vec![];
+    for listen_address in &args.listen_address {
+        let incoming = AddrIncoming::bind(listen_address)
+            .with_context(|| format!("Couldn't listen on {listen_address}"))?;
+        info!("Listening on {listen_address}");
+        incomings.push(incoming);
+    }
+    if incomings.is_empty() {
+        bail!("No address to listen on");
+    }
+    let mut servers = JoinSet::new();
+    for incoming in incomings {
+        servers.spawn(serve(incoming, state.clone()));
+    }
+    // The servers only stop on their own due to an error which we'll give up on. Otherwise they
+    // all shut down together once told to.
+    while let Some(result) = servers.join_next().await {
+        result.context("Server crashed")??;
+    }
+    Ok(())
+}
+
+/// Set up the `name`d account, getting an access token for it unless `direct_access_token` is
+/// given.
+async fn connect_account(
+    client: &reqwest::Client,
+    metrics: &metrics::Metrics,
+    retry_policy: &api_communication::RetryPolicy,
+    name: String,
+    site24x7_client_info: site24x7_types::Site24x7ClientInfo,
+    refresh_token: Option<String>,
+    direct_access_token: Option<String>,
+) -> Result<web_service::Account> {
     // An access token is only available for a period of time.
     // We sometimes have to refresh it.
-    let access_token = Arc::new(RwLock::new(
-        api_communication::get_access_token(&CLIENT, &site24x7_client_info, &refresh_token).await?,
-    ));
+    let (access_token, access_token_expires_at) = match (direct_access_token, &refresh_token) {
+        (Some(access_token), _) => (access_token, None),
+        (None, Some(refresh_token)) => {
+            metrics.token_refresh.inc();
+            let access_token = api_communication::get_access_token(
+                client,
+                &site24x7_client_info,
+                refresh_token,
+                retry_policy,
+            )
+            .await?;
+            metrics::set_zoho_api_domain(metrics, &access_token.api_domain);
+            (
+                access_token.access_token,
+                Instant::now().checked_add(access_token.expires_in),
+            )
+        }
+        (None, None) => unreachable!("Either an access token or a refresh token is required"),
+    };
+    Ok(web_service::Account {
+        name,
+        site24x7_client_info,
+        refresh_token,
+        access_token: RwLock::new(access_token),
+        access_token_expires_at: Mutex::new(access_token_expires_at),
+        rate_limited_until: Mutex::new(None),
+    })
+}
+
+/// Serve connections from the Unix domain socket at `path`.
+#[cfg(unix)]
+async fn serve_unix_socket(
+    path: &std::path::Path,
+    state: Arc<web_service::AppState>,
+) -> Result<()> {
+    let listener = tokio::net::UnixListener::bind(path)
+        .with_context(|| format!("Couldn't listen on Unix socket {}", path.display()))?;
+    info!("Listening on Unix socket {}", path.display());
+    let incoming = hyper::server::accept::poll_fn(move |cx| {
+        listener
+            .poll_accept(cx)
+            .map(|accepted| Some(accepted.map(|(stream, _)| stream)))
+    });
+    serve(incoming, state).await
+}
+
+#[cfg(not(unix))]
+async fn serve_unix_socket(
+    _path: &std::path::Path,
+    _state: Arc<web_service::AppState>,
+) -> Result<()> {
+    anyhow::bail!("Unix sockets aren't supported on this platform")
+}
 
-    let metrics_path = args.metrics_path.to_string();
-    let geolocation_path = args.geolocation_path.to_string();
+/// Serve the web service on every connection from `incoming`.
+async fn serve<I>(incoming: I, state: Arc<web_service::AppState>) -> Result<()>
+where
+    I: Accept,
+    I::Conn: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+    I::Error: Into<Box<dyn StdError + Send + Sync>>,
+{
+    let mut shutdown = state.shutdown.subscribe();
     let make_service = make_service_fn(move |_conn| {
-        let site24x7_client_info = site24x7_client_info.clone();
-        let refresh_token = refresh_token.clone();
-        let access_token = access_token.clone();
-        let metrics_path = metrics_path.clone();
-        let geolocation_path = geolocation_path.clone();
+        let state = state.clone();
         async move {
             Ok::<_, hyper::Error>(service_fn(move |req| {
-                let site24x7_client_info = site24x7_client_info.clone();
-                let refresh_token = refresh_token.clone();
-                let access_token = access_token.clone();
-                let metrics_path = metrics_path.clone();
-                let geolocation_path = geolocation_path.clone();
-                async move {
-                    web_service::hyper_service(
-                        req,
-                        &site24x7_client_info,
-                        &refresh_token,
-                        access_token,
-                        &metrics_path,
-                        &geolocation_path,
-                    )
-                    .await
-                }
+                let state = state.clone();
+                async move { web_service::hyper_service(req, &state).await }
             }))
         }
     });
 
-    let server = Server::bind(&args.listen_address).serve(make_service);
+    let server = Server::builder(incoming)
+        .serve(make_service)
+        .with_graceful_shutdown(async move {
+            // The sender lives in `state` so it's never dropped while we're serving.
+            let _ = shutdown.wait_for(|shutdown| *shutdown).await;
+        });
 
     server.await.context("Server error")
 }
@@ -1,44 +1,743 @@
 //! Module containing functions related to handling metrics.
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
 
-use log::{debug, info};
+use chrono::{DateTime, Utc};
+use clap::crate_version;
+use log::{debug, info, warn};
+use prometheus::core::{Collector, MetricVec, MetricVecBuilder};
 use prometheus::proto::MetricFamily;
-
-use crate::{
-    site24x7_types::{self, CurrentStatusData},
-    MONITOR_LATENCY_SECONDS_GAUGE, MONITOR_UP_GAUGE,
+use prometheus::{
+    Gauge, GaugeVec, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry,
 };
+use regex::Regex;
+use strum::IntoEnumIterator;
+
+use crate::site24x7_types::{self, CurrentStatusData};
+
+/// Options that influence which metrics are set from the Site24x7 data.
+#[derive(Clone, Debug, Default)]
+pub struct MetricsOptions {
+    /// Monitor types that a monitor group (by name) is expected to exclusively contain.
+    pub group_expected_types: HashMap<String, HashSet<String>>,
+    /// Only export monitors whose name matches.
+    pub monitor_include_regex: Option<Regex>,
+    /// Never export monitors whose name matches, even if they match `monitor_include_regex`.
+    pub monitor_exclude_regex: Option<Regex>,
+    /// Only export monitor groups with these names (all if empty). [`NO_MONITOR_GROUP`] stands
+    /// for monitors without a group.
+    pub group_include: HashSet<String>,
+    /// Never export monitor groups with these names, even if they're in `group_include`.
+    pub group_exclude: HashSet<String>,
+    /// Report NaN as the latency of locations that aren't up even if they report a value.
+    pub nan_on_non_up: bool,
+    /// Consider locations stale if they haven't been polled for longer than this.
+    pub staleness_threshold: Option<Duration>,
+    /// Timestamp the up and latency samples of locations with when they were polled.
+    pub use_poll_timestamp: bool,
+}
+
+impl MetricsOptions {
+    /// Return whether the monitor called `monitor_name` passes the name filters.
+    fn is_monitor_included(&self, monitor_name: &str) -> bool {
+        self.monitor_include_regex
+            .as_ref()
+            .is_none_or(|r| r.is_match(monitor_name))
+            && !self
+                .monitor_exclude_regex
+                .as_ref()
+                .is_some_and(|r| r.is_match(monitor_name))
+    }
+
+    /// Return whether the monitor group called `monitor_group` passes the group filters.
+    fn is_group_included(&self, monitor_group: &str) -> bool {
+        (self.group_include.is_empty() || self.group_include.contains(monitor_group))
+            && !self.group_exclude.contains(monitor_group)
+    }
+
+    /// Return `monitors` of `monitor_group` if the group is to be exported or nothing otherwise.
+    fn included_monitors<'a>(
+        &self,
+        monitors: &'a [site24x7_types::MonitorMaybe],
+        monitor_group: &str,
+    ) -> &'a [site24x7_types::MonitorMaybe] {
+        if self.is_group_included(monitor_group) {
+            monitors
+        } else {
+            &[]
+        }
+    }
+}
+
+/// Value of the `monitor_group` and `monitor_group_id` labels of monitors that don't belong to a
+/// monitor group on Site24x7.
+///
+/// Dashboards and `group_include` rely on this so it must not change.
+pub const NO_MONITOR_GROUP: &str = "";
+
+/// Labels of the metrics that have one series per monitor.
+///
+/// `monitor_group_id` is added after these if enabled.
+const MONITOR_LABELS: &[&str] = &[
+    "monitor_type",
+    "monitor_name",
+    "monitor_id",
+    "monitor_group",
+];
+
+/// Default upper bounds of the buckets of `site24x7_monitor_latency_seconds_histogram`.
+pub const DEFAULT_LATENCY_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Settings that determine the names, labels or buckets of the metrics and therefore can't change
+/// once they're created.
+#[derive(Clone, Debug)]
+pub struct MetricsSettings {
+    /// Prefix for the names of all metrics unless it's empty.
+    pub namespace: String,
+    /// Upper bounds of the buckets that observed latencies are sorted into.
+    pub latency_buckets: Vec<f64>,
+    /// Add a `monitor_group_id` label to all per-monitor and per-location metrics.
+    pub include_group_id: bool,
+    /// Add a `unit` label with the original unit of the attribute to the attribute and latency
+    /// metrics.
+    pub include_unit_label: bool,
+    /// Add an `account` label to all per-monitor and per-location metrics.
+    pub include_account: bool,
+}
+
+impl Default for MetricsSettings {
+    fn default() -> Self {
+        MetricsSettings {
+            namespace: String::new(),
+            latency_buckets: DEFAULT_LATENCY_BUCKETS.to_vec(),
+            include_group_id: false,
+            include_unit_label: false,
+            include_account: false,
+        }
+    }
+}
+
+/// All metrics exposed by the exporter along with the registry they're registered with.
+///
+/// Clones share their values with the original.
+#[derive(Clone)]
+pub struct Metrics {
+    pub registry: Registry,
+    /// Whether per-monitor and per-location metrics have a `monitor_group_id` label.
+    pub include_group_id: bool,
+    /// Whether the attribute and latency metrics have a `unit` label.
+    pub include_unit_label: bool,
+    /// Whether per-monitor and per-location metrics have an `account` label.
+    pub include_account: bool,
+    pub monitor_up: IntGaugeVec,
+    pub monitor_suspended: IntGaugeVec,
+    pub monitor_maintenance: IntGaugeVec,
+    pub monitor_status_info: IntGaugeVec,
+    pub monitor_latency_seconds: GaugeVec,
+    pub monitor_latency_seconds_histogram: HistogramVec,
+    pub monitor_attribute: GaugeVec,
+    pub monitor_down_locations: IntGaugeVec,
+    pub monitor_locations_total: IntGaugeVec,
+    pub monitor_aggregate_latency_seconds: GaugeVec,
+    pub monitor_step_latency_seconds: GaugeVec,
+    pub monitor_availability_percent: GaugeVec,
+    pub monitor_stale: IntGaugeVec,
+    pub monitor_last_poll_age_seconds: GaugeVec,
+    /// Isn't registered as it's only used to timestamp samples with, see [`set_poll_timestamps`].
+    pub monitor_last_poll_timestamp_seconds: GaugeVec,
+    pub monitor_tag: IntGaugeVec,
+    pub zoho_api_domain_info: IntGaugeVec,
+    pub group_type_violation: IntGaugeVec,
+    pub monitor_group_up: IntGaugeVec,
+    pub access_token_expiry_seconds: Gauge,
+    pub monitors_total: IntGaugeVec,
+    pub unknown_monitor_type: IntGaugeVec,
+    pub monitor_groups_total: IntGauge,
+    /// Has no labels but is a vector so that it can be removed while there is no data.
+    pub oldest_data_age_seconds: GaugeVec,
+    pub scrape_duration_seconds: Histogram,
+    pub scrape_errors: IntCounterVec,
+    pub parse_error: IntGaugeVec,
+    pub token_refresh: IntCounter,
+    pub token_refresh_failures: IntCounter,
+    pub build_info: IntGaugeVec,
+    pub uptime_seconds: Gauge,
+    pub last_scrape_success: IntGauge,
+    pub last_scrape_timestamp_seconds: Gauge,
+}
+
+impl Metrics {
+    /// Create all metrics according to `settings` and register them with a registry of their
+    /// own.
+    pub fn new(settings: &MetricsSettings) -> prometheus::Result<Self> {
+        let namespace = settings.namespace.as_str();
+        let opts = |name: &str, help: &str| Opts::new(name, help).namespace(namespace);
+        let mut monitor_labels = MONITOR_LABELS.to_vec();
+        if settings.include_group_id {
+            monitor_labels.push("monitor_group_id");
+        }
+        if settings.include_account {
+            monitor_labels.push("account");
+        }
+        let location_labels = [&monitor_labels[..], &["location"]].concat();
+        let mut group_labels = vec!["monitor_group"];
+        if settings.include_account {
+            group_labels.push("account");
+        }
+        let location_and = |label: &'static str| [&location_labels[..], &[label]].concat();
+        let unit_label: &[&str] = if settings.include_unit_label {
+            &["unit"]
+        } else {
+            &[]
+        };
+        let metrics = Metrics {
+            registry: Registry::new(),
+            include_group_id: settings.include_group_id,
+            include_unit_label: settings.include_unit_label,
+            include_account: settings.include_account,
+            monitor_up: IntGaugeVec::new(
+                opts(
+                    "site24x7_monitor_up",
+                    "Current health status of the monitor (1 = UP, 0 = DOWN). \
+                    There is one series per monitor_id and location.",
+                ),
+                &location_labels,
+            )?,
+            monitor_suspended: IntGaugeVec::new(
+                opts(
+                    "site24x7_monitor_suspended",
+                    "Whether the monitor is suspended (1 = suspended, 0 = not suspended). \
+                    There is one series per monitor_id and location.",
+                ),
+                &location_labels,
+            )?,
+            monitor_maintenance: IntGaugeVec::new(
+                opts(
+                    "site24x7_monitor_maintenance",
+                    "Whether the monitor is in scheduled maintenance \
+                    (1 = maintenance, 0 = no maintenance). \
+                    There is one series per monitor_id and location.",
+                ),
+                &location_labels,
+            )?,
+            monitor_status_info: IntGaugeVec::new(
+                opts(
+                    "site24x7_monitor_status_info",
+                    "Whether the monitor currently has the status given by the status label \
+                    (1 = current status, 0 = any other status). \
+                    There is one series per monitor_id, location and possible status.",
+                ),
+                &location_and("status"),
+            )?,
+            monitor_latency_seconds: GaugeVec::new(
+                opts(
+                    "site24x7_monitor_latency_seconds",
+                    "Last measured latency in seconds. \
+                    There is one series per monitor_id and location. \
+                    The attribute label tells what kind of latency is measured \
+                    (e.g. response_time). It's the monitor's attribute_key or its attributeName \
+                    if it has none.",
+                ),
+                &[&location_and("attribute")[..], unit_label].concat(),
+            )?,
+            monitor_latency_seconds_histogram: HistogramVec::new(
+                HistogramOpts::new(
+                    "site24x7_monitor_latency_seconds_histogram",
+                    "Distribution of the latencies measured across all locations in seconds. \
+                    Every value is observed once per time Site24x7 polled the location.",
+                )
+                .namespace(namespace)
+                .buckets(settings.latency_buckets.clone()),
+                &["monitor_type"],
+            )?,
+            monitor_attribute: GaugeVec::new(
+                opts(
+                    "site24x7_monitor_attribute",
+                    "Last measured value of the monitor's primary attribute as reported by \
+                    Site24x7 (e.g. a response time in milliseconds or the CPU usage of a server). \
+                    There is one series per monitor_id and location. \
+                    The attribute_name label tells what is measured (e.g. RESPONSETIME).",
+                ),
+                &[&location_and("attribute_name")[..], unit_label].concat(),
+            )?,
+            monitor_down_locations: IntGaugeVec::new(
+                opts(
+                    "site24x7_monitor_down_locations",
+                    "Number of locations of the monitor whose status isn't UP. \
+                    There is one series per monitor_id.",
+                ),
+                &monitor_labels,
+            )?,
+            monitor_locations_total: IntGaugeVec::new(
+                opts(
+                    "site24x7_monitor_locations_total",
+                    "Number of locations the monitor is checked from. \
+                    There is one series per monitor_id.",
+                ),
+                &monitor_labels,
+            )?,
+            monitor_aggregate_latency_seconds: GaugeVec::new(
+                opts(
+                    "site24x7_monitor_aggregate_latency_seconds",
+                    "Last latency in seconds as aggregated across all locations by Site24x7. \
+                    There is one series per monitor_id.",
+                ),
+                &monitor_labels,
+            )?,
+            monitor_step_latency_seconds: GaugeVec::new(
+                opts(
+                    "site24x7_monitor_step_latency_seconds",
+                    "Last measured latency in seconds of a single step of a transaction. \
+                    There is one series per monitor_id and step_name.",
+                ),
+                &[&monitor_labels[..], &["step_name"]].concat(),
+            )?,
+            monitor_availability_percent: GaugeVec::new(
+                opts(
+                    "site24x7_monitor_availability_percent",
+                    "Last availability in percent of monitors whose attribute is an availability \
+                    rather than a latency as told by their attribute_key or their attributeName \
+                    if they have none. There is one series per monitor_id and location.",
+                ),
+                &location_labels,
+            )?,
+            monitor_stale: IntGaugeVec::new(
+                opts(
+                    "site24x7_monitor_stale",
+                    "Whether Site24x7 hasn't polled the location for longer than \
+                    --monitor.staleness-threshold (1) or has (0). \
+                    Only exported if the threshold is set.",
+                ),
+                &location_labels,
+            )?,
+            monitor_last_poll_age_seconds: GaugeVec::new(
+                opts(
+                    "site24x7_monitor_last_poll_age_seconds",
+                    "Seconds since Site24x7 last polled the location. \
+                    There is one series per monitor_id and location.",
+                ),
+                &location_labels,
+            )?,
+            monitor_last_poll_timestamp_seconds: GaugeVec::new(
+                opts(
+                    "site24x7_monitor_last_poll_timestamp_seconds",
+                    "Unix timestamp of when Site24x7 last polled the location.",
+                ),
+                &location_labels,
+            )?,
+            monitor_tag: IntGaugeVec::new(
+                opts(
+                    "site24x7_monitor_tag",
+                    "Tags of the monitor (always 1). \
+                    There is one series per monitor_id and tag. \
+                    A tag without a colon has an empty tag_value.",
+                ),
+                &[&monitor_labels[..], &["tag_key", "tag_value"]].concat(),
+            )?,
+            zoho_api_domain_info: IntGaugeVec::new(
+                opts(
+                    "site24x7_zoho_api_domain_info",
+                    "Zoho API domain that issued the current access token (always 1).",
+                ),
+                &["api_domain"],
+            )?,
+            group_type_violation: IntGaugeVec::new(
+                opts(
+                    "site24x7_group_type_violation_total",
+                    "Number of monitors in a monitor group whose type differs from the group's \
+                    expected types.",
+                ),
+                &["monitor_group"],
+            )?,
+            monitor_group_up: IntGaugeVec::new(
+                opts(
+                    "site24x7_monitor_group_up",
+                    "Overall health status of the monitor group (1 = UP, 0 = DOWN). \
+                    Only exported for monitor groups that Site24x7 reports a status for.",
+                ),
+                &group_labels,
+            )?,
+            access_token_expiry_seconds: Gauge::with_opts(opts(
+                "site24x7_access_token_expiry_seconds",
+                "Time until the current Zoho access token expires in seconds.",
+            ))?,
+            monitors_total: IntGaugeVec::new(
+                opts(
+                    "site24x7_monitors_total",
+                    "Number of monitors by type regardless of their locations.",
+                ),
+                &["monitor_type"],
+            )?,
+            unknown_monitor_type: IntGaugeVec::new(
+                opts(
+                    "site24x7_unknown_monitor_type",
+                    "Number of monitors of a type that this exporter can't handle.",
+                ),
+                &["monitor_type"],
+            )?,
+            monitor_groups_total: IntGauge::with_opts(opts(
+                "site24x7_monitor_groups_total",
+                "Number of monitor groups.",
+            ))?,
+            oldest_data_age_seconds: GaugeVec::new(
+                opts(
+                    "site24x7_oldest_data_age_seconds",
+                    "Age of the oldest poll time of any monitor or location in seconds.",
+                ),
+                &[],
+            )?,
+            scrape_duration_seconds: Histogram::with_opts(
+                HistogramOpts::new(
+                    "site24x7_scrape_duration_seconds",
+                    "Duration of fetching the current status from the Site24x7 API in seconds.",
+                )
+                .namespace(namespace),
+            )?,
+            scrape_errors: IntCounterVec::new(
+                opts(
+                    "site24x7_scrape_errors_total",
+                    "Number of failed fetches of the current status from the Site24x7 API by \
+                    kind of error.",
+                ),
+                &["kind"],
+            )?,
+            parse_error: IntGaugeVec::new(
+                opts(
+                    "site24x7_parse_error",
+                    "JSON path of the field at which parsing the most recent unparseable response \
+                    failed (always 1).",
+                ),
+                &["path"],
+            )?,
+            token_refresh: IntCounter::with_opts(opts(
+                "site24x7_token_refresh_total",
+                "Number of attempts to get a new access token from Zoho.",
+            ))?,
+            token_refresh_failures: IntCounter::with_opts(opts(
+                "site24x7_token_refresh_failures_total",
+                "Number of failed attempts to get a new access token from Zoho.",
+            ))?,
+            build_info: IntGaugeVec::new(
+                opts(
+                    "site24x7_exporter_build_info",
+                    "Version information about this exporter (always 1).",
+                ),
+                &["version", "rustc_version", "git_commit"],
+            )?,
+            uptime_seconds: Gauge::with_opts(opts(
+                "site24x7_exporter_uptime_seconds",
+                "Time since the exporter was started in seconds.",
+            ))?,
+            last_scrape_success: IntGauge::with_opts(opts(
+                "site24x7_last_scrape_success",
+                "Whether the last fetch of data from Site24x7 succeeded \
+                (1 = success, 0 = failure).",
+            ))?,
+            last_scrape_timestamp_seconds: Gauge::with_opts(opts(
+                "site24x7_last_scrape_timestamp_seconds",
+                "Unix timestamp of the last successful fetch of data from Site24x7.",
+            ))?,
+        };
+        metrics.register()?;
+        Ok(metrics)
+    }
+
+    fn register(&self) -> prometheus::Result<()> {
+        let collectors: Vec<Box<dyn Collector>> = vec![
+            Box::new(self.monitor_up.clone()),
+            Box::new(self.monitor_suspended.clone()),
+            Box::new(self.monitor_maintenance.clone()),
+            Box::new(self.monitor_status_info.clone()),
+            Box::new(self.monitor_latency_seconds.clone()),
+            Box::new(self.monitor_latency_seconds_histogram.clone()),
+            Box::new(self.monitor_attribute.clone()),
+            Box::new(self.monitor_down_locations.clone()),
+            Box::new(self.monitor_locations_total.clone()),
+            Box::new(self.monitor_aggregate_latency_seconds.clone()),
+            Box::new(self.monitor_step_latency_seconds.clone()),
+            Box::new(self.monitor_availability_percent.clone()),
+            Box::new(self.monitor_stale.clone()),
+            Box::new(self.monitor_last_poll_age_seconds.clone()),
+            Box::new(self.monitor_tag.clone()),
+            Box::new(self.zoho_api_domain_info.clone()),
+            Box::new(self.group_type_violation.clone()),
+            Box::new(self.monitor_group_up.clone()),
+            Box::new(self.access_token_expiry_seconds.clone()),
+            Box::new(self.monitors_total.clone()),
+            Box::new(self.unknown_monitor_type.clone()),
+            Box::new(self.monitor_groups_total.clone()),
+            Box::new(self.oldest_data_age_seconds.clone()),
+            Box::new(self.scrape_duration_seconds.clone()),
+            Box::new(self.scrape_errors.clone()),
+            Box::new(self.parse_error.clone()),
+            Box::new(self.token_refresh.clone()),
+            Box::new(self.token_refresh_failures.clone()),
+            Box::new(self.build_info.clone()),
+            Box::new(self.uptime_seconds.clone()),
+            Box::new(self.last_scrape_success.clone()),
+            Box::new(self.last_scrape_timestamp_seconds.clone()),
+        ];
+        for collector in collectors {
+            self.registry.register(collector)?;
+        }
+        Ok(())
+    }
+}
+
+/// Expose information about this build of the exporter.
+pub fn set_build_info(metrics: &Metrics) {
+    metrics
+        .build_info
+        .with_label_values(&[crate_version!(), env!("RUSTC_VERSION"), env!("GIT_COMMIT")])
+        .set(1);
+}
+
+/// Expose the Zoho API domain that issued the current access token.
+pub fn set_zoho_api_domain(metrics: &Metrics, api_domain: &str) {
+    // Only ever expose the domain of the most recent token.
+    metrics.zoho_api_domain_info.reset();
+    metrics
+        .zoho_api_domain_info
+        .with_label_values(&[api_domain])
+        .set(1);
+}
+
+/// Expose `path` as the place where parsing the most recent response failed.
+pub fn set_parse_error_path(metrics: &Metrics, path: &str) {
+    // Only ever expose the most recent error so that broken fields don't pile up.
+    metrics.parse_error.reset();
+    metrics.parse_error.with_label_values(&[path]).set(1);
+}
+
+/// Return how many seconds are left at `now` until `expires_at`.
+///
+/// Expired tokens report 0 rather than a negative time.
+fn access_token_expiry_seconds(expires_at: Instant, now: Instant) -> f64 {
+    expires_at.saturating_duration_since(now).as_secs_f64()
+}
+
+/// Expose how long the access token expiring at `expires_at` remains valid.
+pub fn set_access_token_expiry(metrics: &Metrics, expires_at: Instant) {
+    metrics
+        .access_token_expiry_seconds
+        .set(access_token_expiry_seconds(expires_at, Instant::now()));
+}
+
+/// Return the locations of `monitor` with only the most recently polled one of each name.
+///
+/// Site24x7 has been seen to transiently report a location twice and we can only export one of
+/// them. Locations keep the position of their first occurrence.
+fn unique_locations(monitor: &site24x7_types::Monitor) -> Vec<&site24x7_types::Location> {
+    let mut locations: Vec<&site24x7_types::Location> = vec![];
+    for location in &monitor.locations {
+        match locations
+            .iter_mut()
+            .find(|kept| kept.location_name == location.location_name)
+        {
+            Some(kept) => {
+                warn!(
+                    "Monitor '{}' reports location '{}' more than once, keeping the most recent one",
+                    monitor.name, location.location_name
+                );
+                if location.last_polled_time >= kept.last_polled_time {
+                    *kept = location;
+                }
+            }
+            None => locations.push(location),
+        }
+    }
+    locations
+}
 
 /// Set the Prometheus metrics for `monitors`.
 ///
-/// Set `monitor_group` to `""` in case the monitor doesn't belong to a monitor group on Site24x7.
-fn set_metrics_for_monitors(monitors: &[site24x7_types::MonitorMaybe], monitor_group: &str) {
+/// Set `monitor_group` and `monitor_group_id` to [`NO_MONITOR_GROUP`] in case the monitor doesn't
+/// belong to a monitor group on Site24x7. `account` is only used if metrics have an `account` label.
+fn set_metrics_for_monitors(
+    metrics: &Metrics,
+    monitors: &[site24x7_types::MonitorMaybe],
+    account: &str,
+    monitor_group: &str,
+    monitor_group_id: &str,
+    options: &MetricsOptions,
+) {
+    let now = Utc::now();
     for monitor_maybe in monitors {
         let monitor_type = monitor_maybe.to_string();
-        let monitor = match monitor_maybe {
-            site24x7_types::MonitorMaybe::URL(m)
-            | site24x7_types::MonitorMaybe::HOMEPAGE(m)
-            | site24x7_types::MonitorMaybe::RESTAPI(m)
-            | site24x7_types::MonitorMaybe::REALBROWSER(m) => m,
-            site24x7_types::MonitorMaybe::Unknown => continue,
+        let Some(monitor) = monitor_maybe.monitor() else {
+            continue;
+        };
+        if !options.is_monitor_included(&monitor.name) {
+            continue;
+        }
+        let locations = unique_locations(monitor);
+        let down_locations = locations
+            .iter()
+            .filter(|location| location.status != site24x7_types::Status::Up)
+            .count();
+        let mut monitor_labels = vec![
+            monitor_type.as_str(),
+            &monitor.name,
+            &monitor.monitor_id,
+            monitor_group,
+        ];
+        if metrics.include_group_id {
+            monitor_labels.push(monitor_group_id);
+        }
+        if metrics.include_account {
+            monitor_labels.push(account);
+        }
+        let unit_labels = if metrics.include_unit_label {
+            vec![monitor.unit.as_deref().unwrap_or_default()]
+        } else {
+            vec![]
         };
-        for location in &monitor.locations {
+        metrics
+            .monitor_down_locations
+            .with_label_values(&monitor_labels)
+            .set(down_locations as i64);
+        metrics
+            .monitor_locations_total
+            .with_label_values(&monitor_labels)
+            .set(locations.len() as i64);
+
+        let measures_latency = monitor_maybe.measures_latency() && !measures_availability(monitor);
+
+        // Like for locations, a missing value keeps the previous one.
+        if let (true, Some(attribute_value)) = (measures_latency, monitor.attribute_value) {
+            metrics
+                .monitor_aggregate_latency_seconds
+                .with_label_values(&monitor_labels)
+                .set(attribute_value / 1000.0);
+        }
+        for tag in &monitor.tags {
+            metrics
+                .monitor_tag
+                .with_label_values(&[&monitor_labels[..], &[&tag.key, &tag.value]].concat())
+                .set(1);
+        }
+        for step in &monitor.steps {
+            if let Some(attribute_value) = step.attribute_value {
+                metrics
+                    .monitor_step_latency_seconds
+                    .with_label_values(&[&monitor_labels[..], &[&step.step_name]].concat())
+                    .set(attribute_value / 1000.0);
+            }
+        }
+        for location in locations {
             debug!(
-                "Setting site24x7_monitor_up{{monitor_type=\"{}\",monitor_name=\"{}\",monitor_group=\"{}\",location=\"{}\"}} {}",
+                "Setting site24x7_monitor_up{{monitor_type=\"{}\",monitor_name=\"{}\",monitor_id=\"{}\",monitor_group=\"{}\",location=\"{}\"}} {}",
                 &monitor_type,
                 &monitor.name,
+                &monitor.monitor_id,
                 &monitor_group,
                 &location.location_name,
                 location.clone().status as i64
             );
-            let up_gauge = MONITOR_UP_GAUGE.with_label_values(&[
-                &monitor_type,
-                &monitor.name,
-                monitor_group,
-                &location.location_name,
-            ]);
+            let location_labels = [&monitor_labels[..], &[&location.location_name]].concat();
+            // Whether this is a measurement we haven't seen before, as opposed to the same data
+            // being applied again, e.g. on a cache hit or as Site24x7 hasn't polled again since.
+            let mut newly_polled = false;
+            let up_gauge = metrics.monitor_up.with_label_values(&location_labels);
             up_gauge.set(location.clone().status as i64);
 
+            // Dedicated flags make it easy to silence alerts for monitors that aren't expected to
+            // be up anyway.
+            metrics
+                .monitor_suspended
+                .with_label_values(&location_labels)
+                .set((location.status == site24x7_types::Status::Suspended) as i64);
+            metrics
+                .monitor_maintenance
+                .with_label_values(&location_labels)
+                .set((location.status == site24x7_types::Status::Maintenance) as i64);
+
+            // Every possible status gets a series so that a change of status doesn't leave the
+            // previous one behind.
+            for status in site24x7_types::Status::iter() {
+                metrics
+                    .monitor_status_info
+                    .with_label_values(&[&location_labels[..], &[&status.to_string()]].concat())
+                    .set((location.status == status) as i64);
+            }
+
+            if let Some(last_polled_time) = location.last_polled_time_utc() {
+                let age = (now - last_polled_time).to_std().unwrap_or_else(|_| {
+                    debug!(
+                        "Location {} of monitor {} was polled in the future at {}, assuming now",
+                        location.location_name, monitor.name, last_polled_time
+                    );
+                    Duration::ZERO
+                });
+                metrics
+                    .monitor_last_poll_age_seconds
+                    .with_label_values(&location_labels)
+                    .set(age.as_secs_f64());
+                let poll_timestamp_gauge = metrics
+                    .monitor_last_poll_timestamp_seconds
+                    .with_label_values(&location_labels);
+                let poll_timestamp = last_polled_time.timestamp_millis() as f64 / 1000.0;
+                newly_polled = poll_timestamp_gauge.get() != poll_timestamp;
+                poll_timestamp_gauge.set(poll_timestamp);
+
+                if let Some(threshold) = options.staleness_threshold {
+                    let stale = age > threshold;
+                    if stale {
+                        warn!(
+                            "Location {} of monitor {} hasn't been polled since {}",
+                            location.location_name, monitor.name, last_polled_time
+                        );
+                    }
+                    metrics
+                        .monitor_stale
+                        .with_label_values(&location_labels)
+                        .set(stale as i64);
+                }
+            }
+
+            // The raw value is exported for every monitor as not all of them measure latency.
+            // There's no telling what a sensible value for a missing one would be so we'll keep
+            // the previous one.
+            if let Some(attribute_value) = location.attribute_value {
+                debug!(
+                    "Setting site24x7_monitor_attribute{{monitor_type=\"{}\",monitor_name=\"{}\",monitor_id=\"{}\",monitor_group=\"{}\",location=\"{}\",attribute_name=\"{}\"}} {}",
+                    &monitor_type,
+                    &monitor.name,
+                    &monitor.monitor_id,
+                    &monitor_group,
+                    &location.location_name,
+                    &monitor.attribute_name,
+                    attribute_value,
+                );
+                metrics
+                    .monitor_attribute
+                    .with_label_values(
+                        &[
+                            &location_labels[..],
+                            &[&monitor.attribute_name],
+                            &unit_labels,
+                        ]
+                        .concat(),
+                    )
+                    .set(attribute_value);
+            }
+
+            // Availabilities are percentages already and mustn't be mistaken for latencies.
+            if measures_availability(monitor) {
+                if let Some(attribute_value) = location.attribute_value {
+                    metrics
+                        .monitor_availability_percent
+                        .with_label_values(&location_labels)
+                        .set(attribute_value);
+                }
+                continue;
+            }
+
+            if !measures_latency {
+                continue;
+            }
+
             // There is a special case where sometimes locations don't report an
             // `attribute_value` even though they are up. This appears to happen
             // in case monitor hasn't managed to poll new data for some time.
@@ -54,146 +753,579 @@ fn set_metrics_for_monitors(monitors: &[site24x7_types::MonitorMaybe], monitor_g
 
             // The original gauge is in milliseconds. Convert it to seconds first as prometheus wants
             // its time series data in seconds.
-            let attribute_value = if let Some(attribute_value) = location.attribute_value {
-                attribute_value as f64 / 1000.0
-            } else if location.status != site24x7_types::Status::Up {
-                // We'll report +Inf instead of 0 if the monitor is down as a latency of 0 might
-                // be misleading.
-                // See https://prometheus.io/docs/practices/instrumentation/#avoid-missing-metrics
-                f64::INFINITY
-            } else {
-                0.0
-            };
+            let attribute_value =
+                if options.nan_on_non_up && location.status != site24x7_types::Status::Up {
+                    // A location in trouble might still report a value but it's not necessarily a
+                    // meaningful one.
+                    f64::NAN
+                } else if let Some(attribute_value) = location.attribute_value {
+                    attribute_value / 1000.0
+                } else if location.status != site24x7_types::Status::Up {
+                    // We'll report +Inf instead of 0 if the monitor is down as a latency of 0 might
+                    // be misleading.
+                    // See https://prometheus.io/docs/practices/instrumentation/#avoid-missing-metrics
+                    f64::INFINITY
+                } else {
+                    0.0
+                };
             debug!(
-                "Setting site24x7_monitor_latency_seconds{{monitor_type=\"{}\",monitor_name=\"{}\",monitor_group=\"{}\",location=\"{}\"}} {}",
+                "Setting site24x7_monitor_latency_seconds{{monitor_type=\"{}\",monitor_name=\"{}\",monitor_id=\"{}\",monitor_group=\"{}\",location=\"{}\",attribute=\"{}\"}} {}",
                 &monitor_type,
                 &monitor.name,
+                &monitor.monitor_id,
                 &monitor_group,
                 &location.location_name,
+                attribute_label(monitor),
                 attribute_value,
             );
-            let latency_gauge = MONITOR_LATENCY_SECONDS_GAUGE.with_label_values(&[
-                &monitor_type,
-                &monitor.name,
-                monitor_group,
-                &location.location_name,
-            ]);
+            let latency_gauge = metrics.monitor_latency_seconds.with_label_values(
+                &[
+                    &location_labels[..],
+                    &[attribute_label(monitor)],
+                    &unit_labels,
+                ]
+                .concat(),
+            );
             latency_gauge.set(attribute_value);
+            // Without a poll time there's no telling whether a value was already observed so it
+            // isn't.
+            if newly_polled && location.attribute_value.is_some() && !attribute_value.is_nan() {
+                metrics
+                    .monitor_latency_seconds_histogram
+                    .with_label_values(&[&monitor_type])
+                    .observe(attribute_value);
+            }
         }
     }
 }
 
-/// Return whether `monitors` contains a monitor having given attributes.
+/// Return the name of the attribute that a monitor measures.
+///
+/// For instance, URL monitors measure `response_time` whereas REALBROWSER monitors measure
+/// `transaction_time`. The `attribute_key` is more specific than the `attributeName` so it wins if
+/// they disagree.
+fn attribute_label(monitor: &site24x7_types::Monitor) -> &str {
+    monitor
+        .attribute_key
+        .as_deref()
+        .unwrap_or(&monitor.attribute_name)
+}
+
+/// Return whether a monitor reports its availability in percent instead of a latency.
+fn measures_availability(monitor: &site24x7_types::Monitor) -> bool {
+    attribute_label(monitor).eq_ignore_ascii_case("availability")
+}
+
+/// Return whether `monitors` contains an exported monitor having given attributes.
+///
+/// `location_name`, `attribute`, `attribute_name`, `step_name` and `unit` are only checked if given
+/// as not all metrics carry them.
+#[allow(clippy::too_many_arguments)]
 fn has_monitor_with_label_values(
     monitors: &[site24x7_types::MonitorMaybe],
+    options: &MetricsOptions,
     monitor_type: &str,
     monitor_name: &str,
-    location_name: &str,
+    monitor_id: &str,
+    location_name: Option<&str>,
+    attribute: Option<&str>,
+    attribute_name: Option<&str>,
+    step_name: Option<&str>,
+    unit: Option<&str>,
+    tag: Option<(&str, &str)>,
 ) -> bool {
-    for monitor_maybe in monitors {
-        let monitor = match monitor_maybe {
-            site24x7_types::MonitorMaybe::URL(m)
-            | site24x7_types::MonitorMaybe::HOMEPAGE(m)
-            | site24x7_types::MonitorMaybe::RESTAPI(m)
-            | site24x7_types::MonitorMaybe::REALBROWSER(m) => m,
-            site24x7_types::MonitorMaybe::Unknown => continue,
+    monitors.iter().any(|monitor_maybe| {
+        let Some(monitor) = monitor_maybe.monitor() else {
+            return false;
         };
-        for location in &monitor.locations {
-            if monitor_type == monitor_maybe.to_string()
-                && monitor_name == monitor.name
-                && location_name == location.location_name
-            {
-                return true;
+        monitor_type == monitor_maybe.to_string()
+            && monitor_name == monitor.name
+            && monitor_id == monitor.monitor_id
+            && location_name.is_none_or(|l| {
+                monitor
+                    .locations
+                    .iter()
+                    .any(|location| l == location.location_name)
+            })
+            && attribute.is_none_or(|a| a == attribute_label(monitor))
+            && attribute_name.is_none_or(|a| a == monitor.attribute_name)
+            && step_name.is_none_or(|s| monitor.steps.iter().any(|step| s == step.step_name))
+            && unit.is_none_or(|u| u == monitor.unit.as_deref().unwrap_or_default())
+            && tag.is_none_or(|(key, value)| {
+                monitor
+                    .tags
+                    .iter()
+                    .any(|tag| key == tag.key && value == tag.value)
+            })
+            && options.is_monitor_included(&monitor.name)
+    })
+}
+
+/// Return the labels of the series in `metric_family` that belong to `monitor_group` but whose
+/// monitor isn't exported anymore because it was deleted or somehow became invalid.
+///
+/// Series labeled with another ID than `monitor_group_id` are stale as well as the group must have
+/// been replaced by another one of the same name. Series of other accounts are left alone.
+fn stale_series<'a>(
+    metric_family: &'a MetricFamily,
+    monitors: &[site24x7_types::MonitorMaybe],
+    account: &str,
+    monitor_group: &str,
+    monitor_group_id: &str,
+    options: &MetricsOptions,
+) -> Vec<HashMap<&'a str, &'a str>> {
+    let mut stale = vec![];
+    for metric in metric_family.get_metric() {
+        let labels: HashMap<_, _> = metric
+            .get_label()
+            .iter()
+            .map(|l| (l.get_name(), l.get_value()))
+            .collect();
+        let label_value = |name: &str| labels.get(name).copied();
+        let (Some(current_monitor_group), Some(monitor_type), Some(monitor_name), Some(monitor_id)) = (
+            label_value("monitor_group"),
+            label_value("monitor_type"),
+            label_value("monitor_name"),
+            label_value("monitor_id"),
+        ) else {
+            warn!(
+                "Not cleaning up {} series with unexpected labels {:?}",
+                metric_family.get_name(),
+                metric.get_label()
+            );
+            continue;
+        };
+        // Skip any metrics that are not in the given `monitor_group` of the given `account`.
+        if current_monitor_group != monitor_group
+            || label_value("account").is_some_and(|a| a != account)
+        {
+            continue;
+        }
+        if label_value("monitor_group_id").is_some_and(|id| id != monitor_group_id)
+            || !has_monitor_with_label_values(
+                monitors,
+                options,
+                monitor_type,
+                monitor_name,
+                monitor_id,
+                label_value("location"),
+                label_value("attribute"),
+                label_value("attribute_name"),
+                label_value("step_name"),
+                label_value("unit"),
+                label_value("tag_key").zip(label_value("tag_value")),
+            )
+        {
+            stale.push(labels);
+        }
+    }
+    stale
+}
+
+/// Remove the stale series of `monitor_group` from `metric_vec`.
+fn cleanup_metric_vec<T: MetricVecBuilder>(
+    metric_vec: &MetricVec<T>,
+    monitors: &[site24x7_types::MonitorMaybe],
+    account: &str,
+    monitor_group: &str,
+    monitor_group_id: &str,
+    options: &MetricsOptions,
+) {
+    for metric_family in metric_vec.collect() {
+        for labels in stale_series(
+            &metric_family,
+            monitors,
+            account,
+            monitor_group,
+            monitor_group_id,
+            options,
+        ) {
+            info!(
+                "Cleaning up now-missing metric {}{:?}",
+                metric_family.get_name(),
+                labels
+            );
+            if let Err(e) = metric_vec.remove(&labels) {
+                warn!("Couldn't clean up metric: {e}");
             }
         }
     }
-    false
 }
 
-/// Clean up metrics that were deleted or somehow became invalid.
+/// Clean up per-monitor and per-location metrics of monitors that were deleted or somehow became
+/// invalid.
 fn cleanup_metrics_for_monitors(
-    metric_families: &[MetricFamily],
+    metrics: &Metrics,
     monitors: &[site24x7_types::MonitorMaybe],
+    account: &str,
     monitor_group: &str,
+    monitor_group_id: &str,
+    options: &MetricsOptions,
 ) {
-    for metric_family in metric_families {
+    // A macro rather than a closure as the metric vectors are of different types.
+    macro_rules! cleanup {
+        ($($metric:ident),*) => {
+            $(
+                cleanup_metric_vec(
+                    &metrics.$metric,
+                    monitors,
+                    account,
+                    monitor_group,
+                    monitor_group_id,
+                    options,
+                );
+            )*
+        };
+    }
+    cleanup!(
+        monitor_up,
+        monitor_suspended,
+        monitor_maintenance,
+        monitor_status_info,
+        monitor_down_locations,
+        monitor_locations_total,
+        monitor_stale,
+        monitor_tag,
+        monitor_aggregate_latency_seconds,
+        monitor_step_latency_seconds,
+        monitor_last_poll_age_seconds,
+        monitor_last_poll_timestamp_seconds,
+        monitor_availability_percent,
+        monitor_latency_seconds,
+        monitor_attribute
+    );
+}
+
+/// Timestamp the up and latency samples in `metric_families` with when Site24x7 polled their
+/// location so that Prometheus records when they were measured rather than scraped.
+///
+/// Samples of locations whose poll time we don't know are left alone.
+pub fn set_poll_timestamps(metrics: &Metrics, metric_families: &mut [MetricFamily]) {
+    let mut poll_timestamps_ms = HashMap::new();
+    for metric_family in metrics.monitor_last_poll_timestamp_seconds.collect() {
         for metric in metric_family.get_metric() {
-            // Skip any metrics that are not in the given `monitor_group`.
-            let current_monitor_group = metric
-                .get_label()
-                .iter()
-                .find(|l| l.get_name() == "monitor_group")
-                .unwrap()
-                .get_value();
-            if current_monitor_group != monitor_group {
-                continue;
-            }
-            let monitor_type = metric
-                .get_label()
-                .iter()
-                .find(|l| l.get_name() == "monitor_type")
-                .unwrap()
-                .get_value();
-            let monitor_name = metric
+            let labels: Vec<_> = metric
                 .get_label()
                 .iter()
-                .find(|l| l.get_name() == "monitor_name")
-                .unwrap()
-                .get_value();
-            let location_name = metric
+                .map(|l| (l.get_name().to_string(), l.get_value().to_string()))
+                .collect();
+            let timestamp_ms = (metric.get_gauge().get_value() * 1000.0) as i64;
+            poll_timestamps_ms.insert(labels, timestamp_ms);
+        }
+    }
+
+    let names = [
+        metrics.monitor_up.desc()[0].fq_name.as_str(),
+        metrics.monitor_latency_seconds.desc()[0].fq_name.as_str(),
+    ];
+    for metric_family in metric_families
+        .iter_mut()
+        .filter(|mf| names.contains(&mf.get_name()))
+    {
+        for metric in metric_family.mut_metric().iter_mut() {
+            // Latencies have labels beyond those of their location which we'll ignore.
+            let location_labels: Vec<_> = metric
                 .get_label()
                 .iter()
-                .find(|l| l.get_name() == "location")
-                .unwrap()
-                .get_value();
-            if !has_monitor_with_label_values(monitors, monitor_type, monitor_name, location_name) {
-                let mut labels = HashMap::new();
-                labels.insert("monitor_type", monitor_type);
-                labels.insert("monitor_name", monitor_name);
-                labels.insert("monitor_group", monitor_group);
-                labels.insert("location", location_name);
-                if metric_family.get_name() == "site24x7_monitor_up" {
-                    info!("Cleaning up now-missing metric site24x7_monitor_up{{monitor_type=\"{}\",monitor_name=\"{}\",monitor_group=\"{}\",location=\"{}\"}}",
-                        monitor_type,
-                        monitor_name,
-                        monitor_group,
-                        location_name,
-                    );
-                    MONITOR_UP_GAUGE.remove(&labels).unwrap();
-                } else if metric_family.get_name() == "site24x7_monitor_latency_seconds" {
-                    info!("Cleaning up now-missing metric site24x7_monitor_latency_seconds{{monitor_type=\"{}\",monitor_name=\"{}\",monitor_group=\"{}\",location=\"{}\"}}",
-                        monitor_type,
-                        monitor_name,
-                        monitor_group,
-                        location_name,
-                    );
-                    MONITOR_LATENCY_SECONDS_GAUGE.remove(&labels).unwrap();
-                }
+                .filter(|l| !["attribute", "unit"].contains(&l.get_name()))
+                .map(|l| (l.get_name().to_string(), l.get_value().to_string()))
+                .collect();
+            if let Some(timestamp_ms) = poll_timestamps_ms.get(&location_labels) {
+                metric.set_timestamp_ms(*timestamp_ms);
             }
         }
     }
 }
 
+/// Count the monitors in `monitors` that are not of any of the `expected_types`.
+fn count_type_violations(
+    monitors: &[site24x7_types::MonitorMaybe],
+    expected_types: &HashSet<String>,
+) -> i64 {
+    monitors
+        .iter()
+        // We can't tell the actual type of monitors we don't know so we won't count them.
+        .filter(|m| !matches!(m, site24x7_types::MonitorMaybe::Unknown(_)))
+        .filter(|m| !expected_types.contains(&m.to_string()))
+        .count() as i64
+}
+
+/// Return all monitor groups in `monitor_groups` including their nested subgroups along with their
+/// names.
+///
+/// Subgroups are named after the path leading to them, e.g. `parent/child`.
+fn flatten_monitor_groups(
+    monitor_groups: &[site24x7_types::MonitorGroup],
+) -> Vec<(String, &site24x7_types::MonitorGroup)> {
+    fn flatten_into<'a>(
+        monitor_groups: &'a [site24x7_types::MonitorGroup],
+        parent_name: Option<&str>,
+        flattened: &mut Vec<(String, &'a site24x7_types::MonitorGroup)>,
+    ) {
+        for monitor_group in monitor_groups {
+            let name = match parent_name {
+                Some(parent_name) => format!("{parent_name}/{}", monitor_group.group_name),
+                None => monitor_group.group_name.clone(),
+            };
+            flattened.push((name.clone(), monitor_group));
+            flatten_into(&monitor_group.subgroups, Some(&name), flattened);
+        }
+    }
+
+    let mut flattened = vec![];
+    flatten_into(monitor_groups, None, &mut flattened);
+    flattened
+}
+
+/// Count all monitors in `current_status_data` by their type.
+fn count_monitors_by_type(current_status_data: &CurrentStatusData) -> HashMap<String, i64> {
+    let monitor_groups = flatten_monitor_groups(&current_status_data.monitor_groups);
+    let monitors = current_status_data.monitors.iter().chain(
+        monitor_groups
+            .iter()
+            .flat_map(|(_, group)| group.monitors.iter()),
+    );
+    let mut counts = HashMap::new();
+    for monitor_maybe in monitors {
+        *counts.entry(monitor_maybe.to_string()).or_default() += 1;
+    }
+    counts
+}
+
+/// Describe how many monitors and monitor groups there are in `current_status_data`.
+pub fn describe_current_status(current_status_data: &CurrentStatusData) -> String {
+    let mut counts: Vec<_> = count_monitors_by_type(current_status_data)
+        .into_iter()
+        .collect();
+    counts.sort();
+    let total: i64 = counts.iter().map(|(_, count)| count).sum();
+    let by_type = counts
+        .iter()
+        .map(|(monitor_type, count)| format!("{monitor_type}: {count}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!(
+        "{total} monitors ({by_type}) in {} monitor groups",
+        flatten_monitor_groups(&current_status_data.monitor_groups).len()
+    )
+}
+
+/// Count the monitors of types we don't know how to handle by their type so that it's easy to tell
+/// which ones are missing.
+fn count_unknown_monitor_types(current_status_data: &CurrentStatusData) -> HashMap<String, i64> {
+    let monitor_groups = flatten_monitor_groups(&current_status_data.monitor_groups);
+    let monitors = current_status_data.monitors.iter().chain(
+        monitor_groups
+            .iter()
+            .flat_map(|(_, group)| group.monitors.iter()),
+    );
+    let mut counts = HashMap::new();
+    for monitor_maybe in monitors {
+        if let site24x7_types::MonitorMaybe::Unknown(monitor_type) = monitor_maybe {
+            debug!("Skipping monitor of unknown type {monitor_type}");
+            *counts.entry(monitor_type.clone()).or_default() += 1;
+        }
+    }
+    counts
+}
+
+/// Return the age of the oldest poll time of any monitor or location relative to `now`.
+///
+/// Returns `None` if there are no poll times at all.
+fn oldest_data_age_seconds(
+    current_status_data: &CurrentStatusData,
+    now: DateTime<Utc>,
+) -> Option<f64> {
+    let monitor_groups = flatten_monitor_groups(&current_status_data.monitor_groups);
+    let monitors = current_status_data.monitors.iter().chain(
+        monitor_groups
+            .iter()
+            .flat_map(|(_, group)| group.monitors.iter()),
+    );
+    monitors
+        .filter_map(|m| m.monitor())
+        .flat_map(|m| {
+            m.locations
+                .iter()
+                .map(|l| l.last_polled_time)
+                .chain(std::iter::once(m.last_polled_time))
+        })
+        .flatten()
+        .min()
+        // Don't report negative ages in case of clock skew.
+        .map(|oldest| (now - oldest.with_timezone(&Utc)).num_milliseconds().max(0) as f64 / 1000.0)
+}
+
 /// Update metrics based on previously gathered data from /current_status API.
-pub fn update_metrics_from_current_status(current_status_data: &CurrentStatusData) {
-    // Clean up monitors that were removed.
-    let metric_families = prometheus::gather();
+pub fn update_metrics_from_current_status(
+    metrics: &Metrics,
+    current_status_data: &CurrentStatusData,
+    options: &MetricsOptions,
+) {
+    update_metrics_from_accounts(metrics, &[("", current_status_data)], options);
+}
+
+/// Update the per-monitor and per-location metrics of a single `account`.
+fn update_monitor_metrics(
+    metrics: &Metrics,
+    account: &str,
+    current_status_data: &CurrentStatusData,
+    options: &MetricsOptions,
+) {
+    // Subgroups are treated just like top-level groups apart from their name.
+    let monitor_groups = flatten_monitor_groups(&current_status_data.monitor_groups);
 
-    cleanup_metrics_for_monitors(&metric_families, &current_status_data.monitors, "");
-    for monitor_group in &current_status_data.monitor_groups {
+    // Clean up monitors that were removed.
+    // Monitors that are filtered out are treated as removed so that they don't linger.
+    let monitors = options.included_monitors(&current_status_data.monitors, NO_MONITOR_GROUP);
+    cleanup_metrics_for_monitors(
+        metrics,
+        monitors,
+        account,
+        NO_MONITOR_GROUP,
+        NO_MONITOR_GROUP,
+        options,
+    );
+    for (group_name, group) in &monitor_groups {
         cleanup_metrics_for_monitors(
-            &metric_families,
-            &monitor_group.monitors,
-            &monitor_group.group_name,
+            metrics,
+            options.included_monitors(&group.monitors, group_name),
+            account,
+            group_name,
+            &group.group_id,
+            options,
         );
     }
 
     // Monitors can either be in a flat list of plain Monitors or they can be inside of a
     // MonitorGroup with is simply a list of monitors.
-    set_metrics_for_monitors(&current_status_data.monitors, "");
+    set_metrics_for_monitors(
+        metrics,
+        monitors,
+        account,
+        NO_MONITOR_GROUP,
+        NO_MONITOR_GROUP,
+        options,
+    );
+
+    for (group_name, group) in &monitor_groups {
+        set_metrics_for_monitors(
+            metrics,
+            options.included_monitors(&group.monitors, group_name),
+            account,
+            group_name,
+            &group.group_id,
+            options,
+        );
+    }
+}
+
+/// Update metrics based on the data gathered from /current_status API of every account in
+/// `accounts` along with the account's name.
+///
+/// Totals cover all accounts together. The names only show up in metrics with an `account` label.
+pub fn update_metrics_from_accounts(
+    metrics: &Metrics,
+    accounts: &[(&str, &CurrentStatusData)],
+    options: &MetricsOptions,
+) {
+    for (account, current_status_data) in accounts {
+        update_monitor_metrics(metrics, account, current_status_data, options);
+    }
+
+    // Totals for capacity planning which are cheaper to query than the per-location series.
+    let mut monitors_by_type = HashMap::new();
+    let mut monitor_groups_total = 0;
+    let mut unknown_monitors_by_type = HashMap::new();
+    for (_, current_status_data) in accounts {
+        for (monitor_type, count) in count_monitors_by_type(current_status_data) {
+            *monitors_by_type.entry(monitor_type).or_default() += count;
+        }
+        monitor_groups_total += flatten_monitor_groups(&current_status_data.monitor_groups).len();
+        for (monitor_type, count) in count_unknown_monitor_types(current_status_data) {
+            *unknown_monitors_by_type.entry(monitor_type).or_default() += count;
+        }
+    }
+    metrics.monitors_total.reset();
+    for (monitor_type, count) in monitors_by_type {
+        metrics
+            .monitors_total
+            .with_label_values(&[&monitor_type])
+            .set(count);
+    }
+    metrics
+        .monitor_groups_total
+        .set(monitor_groups_total as i64);
+    metrics.unknown_monitor_type.reset();
+    for (monitor_type, count) in unknown_monitors_by_type {
+        metrics
+            .unknown_monitor_type
+            .with_label_values(&[&monitor_type])
+            .set(count);
+    }
+
+    // A single staleness watchdog across all accounts.
+    let now = Utc::now();
+    let oldest_data_age = accounts
+        .iter()
+        .filter_map(|(_, current_status_data)| oldest_data_age_seconds(current_status_data, now))
+        .reduce(f64::max);
+    match oldest_data_age {
+        Some(age) => metrics
+            .oldest_data_age_seconds
+            .with_label_values(&[])
+            .set(age),
+        None => metrics.oldest_data_age_seconds.reset(),
+    }
+
+    // Check that groups only contain the monitor types they are expected to contain.
+    let mut violations_by_group: HashMap<&str, i64> = HashMap::new();
+    for (_, current_status_data) in accounts {
+        for (group_name, group) in flatten_monitor_groups(&current_status_data.monitor_groups) {
+            if !options.is_group_included(&group_name) {
+                continue;
+            }
+            if let Some((group_name, expected_types)) =
+                options.group_expected_types.get_key_value(&group_name)
+            {
+                let violations = count_type_violations(&group.monitors, expected_types);
+                if violations > 0 {
+                    info!(
+                        "Monitor group '{}' contains {} monitor(s) not of type {:?}",
+                        group_name, violations, expected_types
+                    );
+                }
+                *violations_by_group.entry(group_name).or_default() += violations;
+            }
+        }
+    }
+    metrics.group_type_violation.reset();
+    for (group_name, violations) in violations_by_group {
+        metrics
+            .group_type_violation
+            .with_label_values(&[group_name])
+            .set(violations);
+    }
 
-    for monitor_group in &current_status_data.monitor_groups {
-        set_metrics_for_monitors(&monitor_group.monitors, &monitor_group.group_name);
+    // Groups come and go along with their status so there's nothing worth keeping.
+    metrics.monitor_group_up.reset();
+    for (account, current_status_data) in accounts {
+        for (group_name, group) in flatten_monitor_groups(&current_status_data.monitor_groups) {
+            let Some(status) = group.status else {
+                continue;
+            };
+            if !options.is_group_included(&group_name) {
+                continue;
+            }
+            let mut group_labels = vec![group_name.as_str()];
+            if metrics.include_account {
+                group_labels.push(account);
+            }
+            metrics
+                .monitor_group_up
+                .with_label_values(&group_labels)
+                .set(status as i64);
+        }
     }
 }
 
@@ -203,16 +1335,23 @@ mod tests {
     use pretty_assertions::assert_eq;
     use prometheus::{Encoder, TextEncoder};
 
+    use crate::openmetrics::OpenMetricsEncoder;
     use crate::parsing::parse_current_status;
 
     use super::*;
 
-    /// Since we're using the global exporter, tests can influence and will influence eachother via
-    /// global state. We'll therefore have to call this function before every test to make sure we
-    /// start with a clean slate.
-    fn clear_state() {
-        MONITOR_UP_GAUGE.reset();
-        MONITOR_LATENCY_SECONDS_GAUGE.reset();
+    /// Gather all metrics of `metrics` except for those whose value depends on the current time.
+    fn gather_without_time_dependent(metrics: &Metrics) -> Vec<MetricFamily> {
+        const TIME_DEPENDENT: &[&str] = &[
+            "site24x7_oldest_data_age_seconds",
+            "site24x7_monitor_last_poll_age_seconds",
+        ];
+        metrics
+            .registry
+            .gather()
+            .into_iter()
+            .filter(|mf| !TIME_DEPENDENT.contains(&mf.get_name()))
+            .collect()
     }
 
     /// Return whether `metric_name` has a label `label_name` having `label_value` in a list `metric_families`.
@@ -237,71 +1376,406 @@ mod tests {
     }
 
     #[test]
-    /// If we get an entirely empty body, we don't want to see any metrics getting created.
-    fn no_metrics_are_created_if_empty_body() -> Result<()> {
-        clear_state();
-        let data = parse_current_status(include_str!("../tests/data/empty_response.json"))?;
-        update_metrics_from_current_status(&data);
-        assert!(prometheus::gather().is_empty());
-        Ok(())
-    }
+    /// The remaining lifetime of the access token should count down to 0.
+    fn access_token_expiry() {
+        use std::time::Duration;
 
-    #[test]
-    /// A simple case where we expect to find two locations in the output.
-    fn simple_two_locations() -> Result<()> {
-        clear_state();
-        let data = parse_current_status(include_str!("../tests/data/simple_two_locations.json"))?;
-        update_metrics_from_current_status(&data);
-        assert_eq!(
-            MONITOR_UP_GAUGE
-                .with_label_values(&["URL", "test", "", "London - UK"])
-                .get(),
-            1
-        );
-        assert_eq!(
-            MONITOR_UP_GAUGE
-                .with_label_values(&["URL", "test", "", "Bucharest - RO"])
-                .get(),
-            1
-        );
+        let now = Instant::now();
+        let expires_at = now + Duration::from_secs(3600);
+        assert_eq!(access_token_expiry_seconds(expires_at, now), 3600.0);
         assert_eq!(
-            MONITOR_LATENCY_SECONDS_GAUGE
-                .with_label_values(&["URL", "test", "", "London - UK"])
-                .get(),
-            0.421
+            access_token_expiry_seconds(expires_at, now + Duration::from_millis(1500)),
+            3598.5
         );
         assert_eq!(
-            MONITOR_LATENCY_SECONDS_GAUGE
-                .with_label_values(&["URL", "test", "", "Bucharest - RO"])
-                .get(),
-            0.757
+            access_token_expiry_seconds(expires_at, now + Duration::from_secs(4000)),
+            0.0
         );
-        Ok(())
     }
 
     #[test]
-    /// A removed location should disappear.
-    fn removed_location_should_disappear() -> Result<()> {
-        clear_state();
-        let data_before =
-            parse_current_status(include_str!("../tests/data/simple_two_locations.json"))?;
-        let data_after =
-            parse_current_status(include_str!("../tests/data/simple_one_location.json"))?;
+    /// A namespace should prefix the names of all metrics.
+    fn namespace_prefixes_metric_names() -> Result<()> {
+        let metrics = Metrics::new(&MetricsSettings {
+            namespace: "myorg".to_string(),
+            ..Default::default()
+        })?;
+        let data = parse_current_status(include_str!("../tests/data/full.json"))?;
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
 
-        // We'll update metrics twice here. `data_before` has two locations while
-        // `data_after` only has one location. We therefore expect the output to only contain a
-        // single location.
-        update_metrics_from_current_status(&data_before);
-        update_metrics_from_current_status(&data_after);
-        let metric_families = prometheus::gather();
+        let metric_families = metrics.registry.gather();
+        let names: Vec<_> = metric_families.iter().map(|mf| mf.get_name()).collect();
+        assert!(names.contains(&"myorg_site24x7_monitor_up"));
+        assert!(names.contains(&"myorg_site24x7_monitor_latency_seconds"));
+        assert!(names.iter().all(|name| name.starts_with("myorg_site24x7_")));
+        Ok(())
+    }
 
+    #[test]
+    /// The build info should carry the crate version.
+    fn build_info_has_version() -> Result<()> {
+        let metrics = Metrics::new(&MetricsSettings::default())?;
+        set_build_info(&metrics);
+        let metric_families = metrics.registry.gather();
         assert!(has_label_with_value(
             &metric_families,
-            "site24x7_monitor_latency_seconds",
-            "location",
-            "Bucharest - RO"
+            "site24x7_exporter_build_info",
+            "version",
+            crate_version!()
         ));
-        // Expect the London locatio to be gone.
+        Ok(())
+    }
+
+    #[test]
+    /// If we get an entirely empty body, we don't want to see any metrics getting created except
+    /// for those without labels which always exist, like the number of monitor groups which is 0
+    /// then.
+    fn no_metrics_are_created_if_empty_body() -> Result<()> {
+        let metrics = Metrics::new(&MetricsSettings::default())?;
+        let data = parse_current_status(include_str!("../tests/data/empty_response.json"))?;
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        let metric_families = metrics.registry.gather();
+        let names: Vec<_> = metric_families.iter().map(|mf| mf.get_name()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "site24x7_access_token_expiry_seconds",
+                "site24x7_exporter_uptime_seconds",
+                "site24x7_last_scrape_success",
+                "site24x7_last_scrape_timestamp_seconds",
+                "site24x7_monitor_groups_total",
+                "site24x7_scrape_duration_seconds",
+                "site24x7_token_refresh_failures_total",
+                "site24x7_token_refresh_total",
+            ]
+        );
+        assert_eq!(metrics.monitor_groups_total.get(), 0);
+        Ok(())
+    }
+
+    #[test]
+    /// Server monitors should export their values as-is instead of as a latency.
+    fn server_monitor() -> Result<()> {
+        let metrics = Metrics::new(&MetricsSettings::default())?;
+        let data = parse_current_status(include_str!("../tests/data/server_monitor.json"))?;
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        assert_eq!(
+            metrics
+                .monitor_attribute
+                .with_label_values(&["SERVER", "test", "01", "", "London - UK", "CPUUSAGE"])
+                .get(),
+            42.5
+        );
+        assert_eq!(
+            metrics
+                .monitor_up
+                .with_label_values(&["SERVER", "test", "01", "", "London - UK"])
+                .get(),
+            1
+        );
+        let metric_families = metrics.registry.gather();
+        assert!(!has_label_with_value(
+            &metric_families,
+            "site24x7_monitor_latency_seconds",
+            "monitor_type",
+            "SERVER"
+        ));
+
+        // The value should be cleaned up along with the monitor.
+        let data = parse_current_status(include_str!("../tests/data/simple_one_monitor.json"))?;
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        let metric_families = metrics.registry.gather();
+        assert!(!has_label_with_value(
+            &metric_families,
+            "site24x7_monitor_attribute",
+            "monitor_type",
+            "SERVER"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    /// Monitors of types we don't know should be counted by their type no matter how often the
+    /// same data is applied.
+    fn unknown_monitor_types_are_counted() -> Result<()> {
+        let metrics = Metrics::new(&MetricsSettings::default())?;
+        let data = parse_current_status(
+            r#"{"data": {"monitors": [{"monitor_type": "DNS"}, {"monitor_type": "DNS"}]}}"#,
+        )?;
+        assert_eq!(
+            data.monitors[0],
+            site24x7_types::MonitorMaybe::Unknown("DNS".to_string())
+        );
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        assert_eq!(
+            metrics
+                .unknown_monitor_type
+                .with_label_values(&["DNS"])
+                .get(),
+            2
+        );
+
+        // Monitors that are gone shouldn't be counted anymore.
+        let data = parse_current_status(include_str!("../tests/data/empty_lists.json"))?;
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        assert!(!has_label_with_value(
+            &metrics.registry.gather(),
+            "site24x7_unknown_monitor_type",
+            "monitor_type",
+            "DNS"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    /// Monitors of unsupported types should be counted wherever they are while the supported ones
+    /// next to them are still exported.
+    fn unknown_monitor_types_in_groups_are_counted() -> Result<()> {
+        let metrics = Metrics::new(&MetricsSettings::default())?;
+        let data = parse_current_status(include_str!("../tests/data/unknown_monitor_type.json"))?;
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        for monitor_type in ["DNS", "SSL_CERT"] {
+            assert_eq!(
+                metrics
+                    .unknown_monitor_type
+                    .with_label_values(&[monitor_type])
+                    .get(),
+                1
+            );
+        }
+        assert_eq!(
+            metrics
+                .monitor_up
+                .with_label_values(&["URL", "frontend", "01", "production", "London - UK"])
+                .get(),
+            1
+        );
+        Ok(())
+    }
+
+    #[test]
+    /// A simple case where we expect to find two locations in the output.
+    fn simple_two_locations() -> Result<()> {
+        let metrics = Metrics::new(&MetricsSettings::default())?;
+        let data = parse_current_status(include_str!("../tests/data/simple_two_locations.json"))?;
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        assert_eq!(
+            metrics
+                .monitor_up
+                .with_label_values(&["URL", "test", "01", "", "London - UK"])
+                .get(),
+            1
+        );
+        assert_eq!(
+            metrics
+                .monitor_up
+                .with_label_values(&["URL", "test", "01", "", "Bucharest - RO"])
+                .get(),
+            1
+        );
+        assert_eq!(
+            metrics
+                .monitor_latency_seconds
+                .with_label_values(&["URL", "test", "01", "", "London - UK", "RESPONSETIME"])
+                .get(),
+            0.421
+        );
+        assert_eq!(
+            metrics
+                .monitor_latency_seconds
+                .with_label_values(&["URL", "test", "01", "", "Bucharest - RO", "RESPONSETIME"])
+                .get(),
+            0.757
+        );
+        Ok(())
+    }
+
+    #[test]
+    /// Monitors sharing a name should still be exported as separate series.
+    fn duplicate_monitor_names_are_kept_apart() -> Result<()> {
+        let metrics = Metrics::new(&MetricsSettings::default())?;
+        let data =
+            parse_current_status(include_str!("../tests/data/duplicate_monitor_names.json"))?;
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        assert_eq!(
+            metrics
+                .monitor_latency_seconds
+                .with_label_values(&["URL", "test", "01", "", "London - UK", "RESPONSETIME"])
+                .get(),
+            0.421
+        );
+        assert_eq!(
+            metrics
+                .monitor_latency_seconds
+                .with_label_values(&["URL", "test", "02", "", "London - UK", "RESPONSETIME"])
+                .get(),
+            0.757
+        );
+        Ok(())
+    }
+
+    #[test]
+    /// Latencies should be labeled with the attribute they measure.
+    fn latency_has_attribute_label() -> Result<()> {
+        let metrics = Metrics::new(&MetricsSettings::default())?;
+        let data = parse_current_status(include_str!("../tests/data/full.json"))?;
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        assert_eq!(
+            metrics
+                .monitor_latency_seconds
+                .with_label_values(&[
+                    "REALBROWSER",
+                    "production (realbrowser)",
+                    "0101",
+                    "production",
+                    "Falkenstein - DE",
+                    "transaction_time"
+                ])
+                .get(),
+            27.458
+        );
+        assert_eq!(
+            metrics
+                .monitor_latency_seconds
+                .with_label_values(&[
+                    "URL",
+                    "production (url)",
+                    "0103",
+                    "production",
+                    "Falkenstein - DE",
+                    "response_time"
+                ])
+                .get(),
+            0.173
+        );
+        Ok(())
+    }
+
+    #[test]
+    /// Every latency should be observed in the bucket it falls into.
+    fn latency_histogram_buckets() -> Result<()> {
+        let metrics = Metrics::new(&MetricsSettings {
+            latency_buckets: vec![0.1, 0.5, 1.0],
+            ..Default::default()
+        })?;
+        let data = parse_current_status(include_str!("../tests/data/simple_two_locations.json"))?;
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        let histogram = metrics
+            .monitor_latency_seconds_histogram
+            .with_label_values(&["URL"]);
+        assert_eq!(histogram.get_sample_count(), 2);
+        assert_eq!(histogram.get_sample_sum(), 0.421 + 0.757);
+
+        let metric_families = metrics.registry.gather();
+        let metric_family = metric_families
+            .iter()
+            .find(|mf| mf.get_name() == "site24x7_monitor_latency_seconds_histogram")
+            .unwrap();
+        let buckets: Vec<_> = metric_family.get_metric()[0]
+            .get_histogram()
+            .get_bucket()
+            .iter()
+            .map(|b| (b.get_upper_bound(), b.get_cumulative_count()))
+            .collect();
+        assert_eq!(buckets, vec![(0.1, 0), (0.5, 1), (1.0, 2)]);
+        Ok(())
+    }
+
+    #[test]
+    /// Latencies should only be observed once no matter how often the same poll is applied.
+    fn latency_histogram_observes_polls_once() -> Result<()> {
+        let metrics = Metrics::new(&MetricsSettings::default())?;
+        let s = include_str!("../tests/data/simple_two_locations.json");
+        let data = parse_current_status(s)?;
+        let sample_count = |metrics: &Metrics| {
+            metrics
+                .monitor_latency_seconds_histogram
+                .with_label_values(&["URL"])
+                .get_sample_count()
+        };
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        assert_eq!(sample_count(&metrics), 2);
+
+        // Both locations were polled again.
+        let data = parse_current_status(
+            &s.replace("2021-01-06T18:53:06+0000", "2021-01-06T18:58:06+0000"),
+        )?;
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        assert_eq!(sample_count(&metrics), 4);
+
+        // A location without a poll time can't be told apart from the last one.
+        let metrics = Metrics::new(&MetricsSettings::default())?;
+        let data = parse_current_status(
+            &include_str!("../tests/data/simple_one_location.json")
+                .replace(r#""last_polled_time": "2021-01-06T18:53:06+0000","#, ""),
+        )?;
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        assert_eq!(sample_count(&metrics), 0);
+        Ok(())
+    }
+
+    #[test]
+    /// The raw value of every monitor should be exported along with the name of its attribute.
+    fn attribute_has_attribute_name_label() -> Result<()> {
+        let metrics = Metrics::new(&MetricsSettings::default())?;
+        let data = parse_current_status(include_str!("../tests/data/full.json"))?;
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        assert_eq!(
+            metrics
+                .monitor_attribute
+                .with_label_values(&[
+                    "REALBROWSER",
+                    "production (realbrowser)",
+                    "0101",
+                    "production",
+                    "Falkenstein - DE",
+                    "TRANSACTIONTIME"
+                ])
+                .get(),
+            27458.0
+        );
+        assert_eq!(
+            metrics
+                .monitor_attribute
+                .with_label_values(&[
+                    "URL",
+                    "production (url)",
+                    "0103",
+                    "production",
+                    "Falkenstein - DE",
+                    "RESPONSETIME"
+                ])
+                .get(),
+            173.0
+        );
+        Ok(())
+    }
+
+    #[test]
+    /// A removed location should disappear.
+    fn removed_location_should_disappear() -> Result<()> {
+        let metrics = Metrics::new(&MetricsSettings::default())?;
+        let data_before =
+            parse_current_status(include_str!("../tests/data/simple_two_locations.json"))?;
+        let data_after =
+            parse_current_status(include_str!("../tests/data/simple_one_location.json"))?;
+
+        // We'll update metrics twice here. `data_before` has two locations while
+        // `data_after` only has one location. We therefore expect the output to only contain a
+        // single location.
+        update_metrics_from_current_status(&metrics, &data_before, &MetricsOptions::default());
+        update_metrics_from_current_status(&metrics, &data_after, &MetricsOptions::default());
+        let metric_families = metrics.registry.gather();
+
+        assert!(has_label_with_value(
+            &metric_families,
+            "site24x7_monitor_latency_seconds",
+            "location",
+            "Bucharest - RO"
+        ));
+        // Expect the London locatio to be gone.
         assert!(!has_label_with_value(
             &metric_families,
             "site24x7_monitor_latency_seconds",
@@ -314,7 +1788,7 @@ mod tests {
     #[test]
     /// A removed monitor should disappear.
     fn removed_monitors_should_disappear() -> Result<()> {
-        clear_state();
+        let metrics = Metrics::new(&MetricsSettings::default())?;
         let data_before =
             parse_current_status(include_str!("../tests/data/simple_two_monitors.json"))?;
         let data_after =
@@ -323,9 +1797,9 @@ mod tests {
         // We'll update metrics twice here. `data_before` has two monitors while
         // `data_after` only has one monitor. We therefore expect the output to only contain a
         // single monitor.
-        update_metrics_from_current_status(&data_before);
-        update_metrics_from_current_status(&data_after);
-        let metric_families = prometheus::gather();
+        update_metrics_from_current_status(&metrics, &data_before, &MetricsOptions::default());
+        update_metrics_from_current_status(&metrics, &data_after, &MetricsOptions::default());
+        let metric_families = metrics.registry.gather();
 
         assert!(has_label_with_value(
             &metric_families,
@@ -343,6 +1817,247 @@ mod tests {
         Ok(())
     }
 
+    /// Return the names of the monitors that end up in `site24x7_monitor_up` for `options`.
+    fn exported_monitor_names(options: &MetricsOptions) -> Result<Vec<&'static str>> {
+        let metrics = Metrics::new(&MetricsSettings::default())?;
+        let data = parse_current_status(include_str!("../tests/data/simple_two_monitors.json"))?;
+        update_metrics_from_current_status(&metrics, &data, options);
+        let metric_families = metrics.registry.gather();
+        Ok(["test1", "test2"]
+            .iter()
+            .copied()
+            .filter(|name| {
+                has_label_with_value(
+                    &metric_families,
+                    "site24x7_monitor_up",
+                    "monitor_name",
+                    name,
+                )
+            })
+            .collect())
+    }
+
+    #[test]
+    /// Only monitors matching the include regex should be exported.
+    fn monitor_include_regex() -> Result<()> {
+        let options = MetricsOptions {
+            monitor_include_regex: Some(Regex::new("1$")?),
+            ..Default::default()
+        };
+        assert_eq!(exported_monitor_names(&options)?, vec!["test1"]);
+        Ok(())
+    }
+
+    #[test]
+    /// Monitors matching the exclude regex should not be exported.
+    fn monitor_exclude_regex() -> Result<()> {
+        let options = MetricsOptions {
+            monitor_exclude_regex: Some(Regex::new("1$")?),
+            ..Default::default()
+        };
+        assert_eq!(exported_monitor_names(&options)?, vec!["test2"]);
+        Ok(())
+    }
+
+    #[test]
+    /// Excludes win over includes and previously exported monitors should disappear once they're
+    /// filtered out.
+    fn monitor_include_and_exclude_regex() -> Result<()> {
+        assert_eq!(
+            exported_monitor_names(&MetricsOptions::default())?,
+            vec!["test1", "test2"]
+        );
+
+        let metrics = Metrics::new(&MetricsSettings::default())?;
+        let data = parse_current_status(include_str!("../tests/data/simple_two_monitors.json"))?;
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        let options = MetricsOptions {
+            monitor_include_regex: Some(Regex::new("^test")?),
+            monitor_exclude_regex: Some(Regex::new("2")?),
+            ..Default::default()
+        };
+        update_metrics_from_current_status(&metrics, &data, &options);
+        let metric_families = metrics.registry.gather();
+        for metric_name in ["site24x7_monitor_up", "site24x7_monitor_latency_seconds"] {
+            assert!(has_label_with_value(
+                &metric_families,
+                metric_name,
+                "monitor_name",
+                "test1"
+            ));
+            assert!(!has_label_with_value(
+                &metric_families,
+                metric_name,
+                "monitor_name",
+                "test2"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Return the monitor groups that end up in `site24x7_monitor_up` for `options`.
+    fn exported_monitor_groups(options: &MetricsOptions) -> Result<Vec<&'static str>> {
+        let metrics = Metrics::new(&MetricsSettings::default())?;
+        let data = parse_current_status(include_str!("../tests/data/full.json"))?;
+        update_metrics_from_current_status(&metrics, &data, options);
+        let metric_families = metrics.registry.gather();
+        Ok(["", "integration", "production"]
+            .iter()
+            .copied()
+            .filter(|group| {
+                has_label_with_value(
+                    &metric_families,
+                    "site24x7_monitor_up",
+                    "monitor_group",
+                    group,
+                )
+            })
+            .collect())
+    }
+
+    #[test]
+    /// Monitor groups should report their status if Site24x7 sends one.
+    fn monitor_group_up() -> Result<()> {
+        let metrics = Metrics::new(&MetricsSettings::default())?;
+        let data = parse_current_status(include_str!("../tests/data/group_status.json"))?;
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        let group_up = |group| metrics.monitor_group_up.with_label_values(&[group]).get();
+        assert_eq!(group_up("production"), 1);
+        assert_eq!(group_up("production/backend"), 0);
+        let metric_families = metrics.registry.gather();
+        assert!(!has_label_with_value(
+            &metric_families,
+            "site24x7_monitor_group_up",
+            "monitor_group",
+            "staging"
+        ));
+
+        // Groups that are filtered out or gone shouldn't leave their status behind.
+        let options = MetricsOptions {
+            group_exclude: ["production/backend".to_string()].into(),
+            ..Default::default()
+        };
+        update_metrics_from_current_status(&metrics, &data, &options);
+        let metric_families = metrics.registry.gather();
+        assert!(!has_label_with_value(
+            &metric_families,
+            "site24x7_monitor_group_up",
+            "monitor_group",
+            "production/backend"
+        ));
+        let data = parse_current_status(include_str!("../tests/data/empty_lists.json"))?;
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        let metric_families = metrics.registry.gather();
+        assert!(!has_label_with_value(
+            &metric_families,
+            "site24x7_monitor_group_up",
+            "monitor_group",
+            "production"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    /// Monitors in subgroups should be exported with the path of their group as its name.
+    fn nested_subgroups() -> Result<()> {
+        let metrics = Metrics::new(&MetricsSettings::default())?;
+        let data = parse_current_status(include_str!("../tests/data/nested_subgroups.json"))?;
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        assert_eq!(
+            metrics
+                .monitor_up
+                .with_label_values(&["URL", "frontend", "01", "production", "London - UK"])
+                .get(),
+            1
+        );
+        assert_eq!(
+            metrics
+                .monitor_latency_seconds
+                .with_label_values(&[
+                    "RESTAPI",
+                    "api",
+                    "02",
+                    "production/backend",
+                    "London - UK",
+                    "RESPONSETIME"
+                ])
+                .get(),
+            0.757
+        );
+        assert_eq!(metrics.monitor_groups_total.get(), 2);
+        assert_eq!(
+            metrics.monitors_total.with_label_values(&["RESTAPI"]).get(),
+            1
+        );
+
+        // Subgroups can be filtered by their full name.
+        let options = MetricsOptions {
+            group_exclude: ["production/backend".to_string()].into(),
+            ..Default::default()
+        };
+        update_metrics_from_current_status(&metrics, &data, &options);
+        let metric_families = metrics.registry.gather();
+        assert!(!has_label_with_value(
+            &metric_families,
+            "site24x7_monitor_up",
+            "monitor_group",
+            "production/backend"
+        ));
+        assert!(has_label_with_value(
+            &metric_families,
+            "site24x7_monitor_up",
+            "monitor_group",
+            "production"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    /// Only included groups should be exported with `""` standing for monitors without a group.
+    fn group_include() -> Result<()> {
+        let options = MetricsOptions {
+            group_include: ["production".to_string(), "".to_string()].into(),
+            ..Default::default()
+        };
+        assert_eq!(exported_monitor_groups(&options)?, vec!["", "production"]);
+        Ok(())
+    }
+
+    #[test]
+    /// Excluded groups should not be exported even if they're also included.
+    fn group_exclude() -> Result<()> {
+        let options = MetricsOptions {
+            group_exclude: ["production".to_string()].into(),
+            ..Default::default()
+        };
+        assert_eq!(exported_monitor_groups(&options)?, vec!["", "integration"]);
+
+        let options = MetricsOptions {
+            group_include: ["production".to_string(), "integration".to_string()].into(),
+            group_exclude: ["integration".to_string()].into(),
+            ..Default::default()
+        };
+        assert_eq!(exported_monitor_groups(&options)?, vec!["production"]);
+        Ok(())
+    }
+
+    #[test]
+    /// Series that don't have the labels we expect should be left alone instead of crashing.
+    fn cleanup_skips_unexpected_labels() {
+        let mut label = prometheus::proto::LabelPair::default();
+        label.set_name("monitor_name".to_string());
+        label.set_value("test".to_string());
+        let mut metric = prometheus::proto::Metric::default();
+        metric.set_label(vec![label].into());
+        let mut metric_family = MetricFamily::default();
+        metric_family.set_name("site24x7_monitor_up".to_string());
+        metric_family.set_metric(vec![metric].into());
+
+        assert!(
+            stale_series(&metric_family, &[], "", "", "", &MetricsOptions::default()).is_empty()
+        );
+    }
+
     #[test]
     /// An update that contains a monitor with a location that doesn't have `attribute_value`
     /// set should not overwrite an existing metric with the same labels.
@@ -351,7 +2066,7 @@ mod tests {
     /// which will cause it to not report an `attribute_value`.
     /// It's better to keep the old value in that case.
     fn keep_old_value_if_update_is_invalid() -> Result<()> {
-        clear_state();
+        let metrics = Metrics::new(&MetricsSettings::default())?;
         let data_before =
             parse_current_status(include_str!("../tests/data/simple_two_locations.json"))?;
         let data_after =
@@ -361,18 +2076,20 @@ mod tests {
         // report their data properly while
         // `data_after` has one location that stops reporting its `attribute_value`.
         // We therefore expect the output after the second update to not be changed.
-        update_metrics_from_current_status(&data_before);
+        update_metrics_from_current_status(&metrics, &data_before, &MetricsOptions::default());
         assert_eq!(
-            MONITOR_LATENCY_SECONDS_GAUGE
-                .with_label_values(&["URL", "test", "", "London - UK"])
+            metrics
+                .monitor_latency_seconds
+                .with_label_values(&["URL", "test", "01", "", "London - UK", "RESPONSETIME"])
                 .get(),
             0.421
         );
 
-        update_metrics_from_current_status(&data_after);
+        update_metrics_from_current_status(&metrics, &data_after, &MetricsOptions::default());
         assert_eq!(
-            MONITOR_LATENCY_SECONDS_GAUGE
-                .with_label_values(&["URL", "test", "", "London - UK"])
+            metrics
+                .monitor_latency_seconds
+                .with_label_values(&["URL", "test", "01", "", "London - UK", "RESPONSETIME"])
                 .get(),
             0.421
         );
@@ -385,71 +2102,827 @@ mod tests {
     ///
     /// See https://prometheus.io/docs/practices/instrumentation/#avoid-missing-metrics
     fn report_inf_for_down_monitor() -> Result<()> {
-        clear_state();
+        let metrics = Metrics::new(&MetricsSettings::default())?;
         let data = parse_current_status(include_str!("../tests/data/down_monitor.json"))?;
-        update_metrics_from_current_status(&data);
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
         assert_eq!(
-            MONITOR_LATENCY_SECONDS_GAUGE
-                .with_label_values(&["URL", "test", "", "London - UK"])
+            metrics
+                .monitor_latency_seconds
+                .with_label_values(&["URL", "test", "01", "", "London - UK", "RESPONSETIME"])
                 .get(),
             27.458
         );
-        assert!(MONITOR_LATENCY_SECONDS_GAUGE
-            .with_label_values(&["URL", "test", "", "Bucharest - RO"])
+        assert!(metrics
+            .monitor_latency_seconds
+            .with_label_values(&["URL", "test", "01", "", "Bucharest - RO", "RESPONSETIME"])
             .get()
             .is_infinite());
 
         Ok(())
     }
 
+    #[test]
+    /// The summary should count monitors in nested groups, too.
+    fn current_status_is_described() -> Result<()> {
+        let data = parse_current_status(include_str!("../tests/data/full.json"))?;
+        assert_eq!(
+            describe_current_status(&data),
+            "5 monitors (HOMEPAGE: 2, REALBROWSER: 1, URL: 2) in 2 monitor groups"
+        );
+        Ok(())
+    }
+
+    #[test]
+    /// The latency aggregated by Site24x7 should be exported in seconds for monitors that have one.
+    fn aggregate_latency() -> Result<()> {
+        let metrics = Metrics::new(&MetricsSettings::default())?;
+        let data = parse_current_status(include_str!("../tests/data/full.json"))?;
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        assert_eq!(
+            metrics
+                .monitor_aggregate_latency_seconds
+                .with_label_values(&["URL", "separate monitor", "00", ""])
+                .get(),
+            0.139
+        );
+        Ok(())
+    }
+
+    #[test]
+    /// Availabilities should be exported in percent as they are instead of as a latency.
+    fn availability_percent() -> Result<()> {
+        let metrics = Metrics::new(&MetricsSettings::default())?;
+        let data = parse_current_status(include_str!("../tests/data/availability_monitor.json"))?;
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        assert_eq!(
+            metrics
+                .monitor_availability_percent
+                .with_label_values(&["URL", "test", "01", "", "London - UK"])
+                .get(),
+            99.5
+        );
+
+        let metric_families = metrics.registry.gather();
+        let names: Vec<_> = metric_families.iter().map(|mf| mf.get_name()).collect();
+        assert!(!names.contains(&"site24x7_monitor_latency_seconds"));
+        assert!(!names.contains(&"site24x7_monitor_aggregate_latency_seconds"));
+        Ok(())
+    }
+
+    #[test]
+    /// The `attribute_key` should decide what a monitor measures unless it has none.
+    fn attribute_key_takes_precedence() -> Result<()> {
+        let data = parse_current_status(include_str!("../tests/data/availability_monitor.json"))?;
+        let monitor = data.monitors[0].monitor().unwrap();
+        let with_attribute = |attribute_name: &str, attribute_key: Option<&str>| {
+            let mut monitor = monitor.clone();
+            monitor.attribute_name = attribute_name.to_string();
+            monitor.attribute_key = attribute_key.map(str::to_string);
+            monitor
+        };
+
+        for (attribute_name, attribute_key, expected_label, expected_availability) in [
+            ("AVAILABILITY", Some("availability"), "availability", true),
+            (
+                "RESPONSETIME",
+                Some("response_time"),
+                "response_time",
+                false,
+            ),
+            ("AVAILABILITY", None, "AVAILABILITY", true),
+            ("RESPONSETIME", None, "RESPONSETIME", false),
+            (
+                "RESPONSETIME",
+                Some("transaction_time"),
+                "transaction_time",
+                false,
+            ),
+            (
+                "AVAILABILITY",
+                Some("response_time"),
+                "response_time",
+                false,
+            ),
+            ("RESPONSETIME", Some("availability"), "availability", true),
+        ] {
+            let monitor = with_attribute(attribute_name, attribute_key);
+            assert_eq!(attribute_label(&monitor), expected_label);
+            assert_eq!(
+                measures_availability(&monitor),
+                expected_availability,
+                "{} {:?}",
+                attribute_name,
+                attribute_key
+            );
+        }
+
+        // Disagreeing attributes still make for a latency labeled by the key.
+        let metrics = Metrics::new(&MetricsSettings::default())?;
+        let data = CurrentStatusData {
+            monitors: vec![site24x7_types::MonitorMaybe::URL(with_attribute(
+                "AVAILABILITY",
+                Some("response_time"),
+            ))],
+            monitor_groups: vec![],
+        };
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        assert_eq!(
+            metrics
+                .monitor_latency_seconds
+                .with_label_values(&["URL", "test", "01", "", "London - UK", "response_time"])
+                .get(),
+            0.0995
+        );
+        let names: Vec<_> = metrics
+            .registry
+            .gather()
+            .iter()
+            .map(|mf| mf.get_name().to_string())
+            .collect();
+        assert!(!names.contains(&"site24x7_monitor_availability_percent".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    /// Up and latency samples should be timestamped with the poll time of their location if asked
+    /// to.
+    fn poll_timestamps() -> Result<()> {
+        let data = parse_current_status(include_str!("../tests/data/simple_one_monitor.json"))?;
+        let metrics = Metrics::new(&MetricsSettings::default())?;
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        let mut metric_families = metrics.registry.gather();
+        assert!(!metric_families
+            .iter()
+            .any(|mf| mf.get_name() == "site24x7_monitor_last_poll_timestamp_seconds"));
+
+        set_poll_timestamps(&metrics, &mut metric_families);
+        // 2021-01-06T18:53:06+0000
+        let poll_timestamp_ms = 1_609_959_186_000;
+        for metric_family in &metric_families {
+            let expected = match metric_family.get_name() {
+                "site24x7_monitor_up" | "site24x7_monitor_latency_seconds" => poll_timestamp_ms,
+                _ => 0,
+            };
+            for metric in metric_family.get_metric() {
+                assert_eq!(
+                    metric.get_timestamp_ms(),
+                    expected,
+                    "{}",
+                    metric_family.get_name()
+                );
+            }
+        }
+
+        let mut buffer = vec![];
+        OpenMetricsEncoder::new().encode(&metric_families, &mut buffer)?;
+        assert!(String::from_utf8(buffer)?.contains(
+            r#"site24x7_monitor_up{location="Bucharest - RO",monitor_group="",monitor_id="01",monitor_name="test1",monitor_type="URL"} 1 1609959186"#
+        ));
+        Ok(())
+    }
+
+    #[test]
+    /// Locations that haven't been polled for too long should be flagged if asked for.
+    fn stale_locations() -> Result<()> {
+        let data = parse_current_status(include_str!("../tests/data/stale_monitor.json"))?;
+
+        let metrics = Metrics::new(&MetricsSettings::default())?;
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        let metric_families = metrics.registry.gather();
+        let names: Vec<_> = metric_families.iter().map(|mf| mf.get_name()).collect();
+        assert!(!names.contains(&"site24x7_monitor_stale"));
+
+        let metrics = Metrics::new(&MetricsSettings::default())?;
+        let options = MetricsOptions {
+            staleness_threshold: Some(Duration::from_secs(60 * 60)),
+            ..Default::default()
+        };
+        update_metrics_from_current_status(&metrics, &data, &options);
+        assert_eq!(
+            metrics
+                .monitor_stale
+                .with_label_values(&["URL", "test", "01", "", "London - UK"])
+                .get(),
+            1
+        );
+        assert_eq!(
+            metrics
+                .monitor_stale
+                .with_label_values(&["URL", "test", "01", "", "Bucharest - RO"])
+                .get(),
+            0
+        );
+
+        // Poll times in the future can't be told apart from now.
+        assert!(
+            metrics
+                .monitor_last_poll_age_seconds
+                .with_label_values(&["URL", "test", "01", "", "London - UK"])
+                .get()
+                > 20.0 * 365.0 * 24.0 * 60.0 * 60.0
+        );
+        assert_eq!(
+            metrics
+                .monitor_last_poll_age_seconds
+                .with_label_values(&["URL", "test", "01", "", "Bucharest - RO"])
+                .get(),
+            0.0
+        );
+        Ok(())
+    }
+
+    #[test]
+    /// Every tag should get a series with everything after the first colon as its value and
+    /// removed tags should disappear.
+    fn monitor_tags() -> Result<()> {
+        let metrics = Metrics::new(&MetricsSettings::default())?;
+        let mut data = parse_current_status(include_str!("../tests/data/full.json"))?;
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        let monitor_labels = ["HOMEPAGE", "integration (homepage)", "0201", "integration"];
+        for (key, value) in [
+            ("test1", ""),
+            ("test2k", "test2v"),
+            ("test3k", "test3v:a:b"),
+        ] {
+            assert_eq!(
+                metrics
+                    .monitor_tag
+                    .with_label_values(&[&monitor_labels[..], &[key, value]].concat())
+                    .get(),
+                1
+            );
+        }
+
+        let site24x7_types::MonitorMaybe::HOMEPAGE(monitor) =
+            &mut data.monitor_groups[1].monitors[0]
+        else {
+            panic!("Expected a HOMEPAGE monitor");
+        };
+        monitor.tags.retain(|tag| tag.key != "test2k");
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        let metric_families = metrics.registry.gather();
+        assert!(has_label_with_value(
+            &metric_families,
+            "site24x7_monitor_tag",
+            "tag_key",
+            "test3k"
+        ));
+        assert!(!has_label_with_value(
+            &metric_families,
+            "site24x7_monitor_tag",
+            "tag_key",
+            "test2k"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    /// Every step of a transaction with a value should get its latency in seconds and steps that
+    /// are gone should disappear.
+    fn step_latency() -> Result<()> {
+        let metrics = Metrics::new(&MetricsSettings::default())?;
+        let mut data = parse_current_status(include_str!("../tests/data/realbrowser_steps.json"))?;
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        let step_latency = |step_name| {
+            metrics
+                .monitor_step_latency_seconds
+                .with_label_values(&["REALBROWSER", "checkout", "01", "", step_name])
+                .get()
+        };
+        assert_eq!(step_latency("Open homepage"), 1.2);
+        assert_eq!(step_latency("Log in"), 3.121);
+        let metric_families = metrics.registry.gather();
+        assert!(!has_label_with_value(
+            &metric_families,
+            "site24x7_monitor_step_latency_seconds",
+            "step_name",
+            "Check out"
+        ));
+
+        let site24x7_types::MonitorMaybe::REALBROWSER(monitor) = &mut data.monitors[0] else {
+            panic!("Expected a REALBROWSER monitor");
+        };
+        monitor.steps.remove(1);
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        let metric_families = metrics.registry.gather();
+        assert!(!has_label_with_value(
+            &metric_families,
+            "site24x7_monitor_step_latency_seconds",
+            "step_name",
+            "Log in"
+        ));
+        assert!(has_label_with_value(
+            &metric_families,
+            "site24x7_monitor_step_latency_seconds",
+            "step_name",
+            "Open homepage"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    /// The original unit should be kept in a label of the attribute and latency metrics if asked
+    /// to.
+    fn unit_label() -> Result<()> {
+        let metrics = Metrics::new(&MetricsSettings {
+            include_unit_label: true,
+            ..Default::default()
+        })?;
+        let data = parse_current_status(include_str!("../tests/data/full.json"))?;
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        let london = ["URL", "separate monitor", "00", "", "London - UK"];
+        assert_eq!(
+            metrics
+                .monitor_latency_seconds
+                .with_label_values(&[&london[..], &["response_time", "ms"]].concat())
+                .get(),
+            0.165
+        );
+        assert_eq!(
+            metrics
+                .monitor_attribute
+                .with_label_values(&[&london[..], &["RESPONSETIME", "ms"]].concat())
+                .get(),
+            165.0
+        );
+        let metric_families = metrics.registry.gather();
+        assert!(!has_label_with_value(
+            &metric_families,
+            "site24x7_monitor_latency_seconds",
+            "unit",
+            ""
+        ));
+
+        let metrics = Metrics::new(&MetricsSettings::default())?;
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        let metric_families = metrics.registry.gather();
+        assert!(!has_label_with_value(
+            &metric_families,
+            "site24x7_monitor_latency_seconds",
+            "unit",
+            "ms"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    /// Monitors should only be labeled with the ID of their group if asked to and standalone
+    /// monitors with an empty one.
+    fn group_id_label() -> Result<()> {
+        let data = parse_current_status(include_str!("../tests/data/full.json"))?;
+
+        let metrics = Metrics::new(&MetricsSettings::default())?;
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        let metric_families = metrics.registry.gather();
+        assert!(metric_families
+            .iter()
+            .all(|mf| mf.get_metric().iter().all(|m| m
+                .get_label()
+                .iter()
+                .all(|l| l.get_name() != "monitor_group_id"))));
+
+        let metrics = Metrics::new(&MetricsSettings {
+            include_group_id: true,
+            ..Default::default()
+        })?;
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        let metric_families = metrics.registry.gather();
+        for metric_name in ["site24x7_monitor_up", "site24x7_monitor_latency_seconds"] {
+            let group_ids: HashSet<_> = metric_families
+                .iter()
+                .find(|mf| mf.get_name() == metric_name)
+                .unwrap()
+                .get_metric()
+                .iter()
+                .map(|m| {
+                    let label_value = |name| {
+                        m.get_label()
+                            .iter()
+                            .find(|l| l.get_name() == name)
+                            .unwrap()
+                            .get_value()
+                    };
+                    (
+                        label_value("monitor_group"),
+                        label_value("monitor_group_id"),
+                    )
+                })
+                .collect();
+            assert_eq!(
+                group_ids,
+                HashSet::from([("production", "01"), ("integration", "02"), ("", "")])
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    /// Every series of a monitor without a group should be labeled with an empty group, which
+    /// dashboards rely on.
+    fn standalone_monitor_group_label() -> Result<()> {
+        let data = parse_current_status(include_str!("../tests/data/simple_one_monitor.json"))?;
+        let metrics = Metrics::new(&MetricsSettings {
+            include_group_id: true,
+            ..Default::default()
+        })?;
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+
+        let mut labeled_metrics = vec![];
+        for mf in metrics.registry.gather() {
+            for m in mf.get_metric() {
+                let group_labels: Vec<_> = m
+                    .get_label()
+                    .iter()
+                    .filter(|l| ["monitor_group", "monitor_group_id"].contains(&l.get_name()))
+                    .map(|l| (l.get_name(), l.get_value()))
+                    .collect();
+                if !group_labels.is_empty() {
+                    assert_eq!(
+                        group_labels,
+                        vec![("monitor_group", ""), ("monitor_group_id", "")],
+                        "{}",
+                        mf.get_name()
+                    );
+                    labeled_metrics.push(mf.get_name().to_string());
+                }
+            }
+        }
+        for metric_name in [
+            "site24x7_monitor_up",
+            "site24x7_monitor_latency_seconds",
+            "site24x7_monitor_attribute",
+            "site24x7_monitor_locations_total",
+        ] {
+            assert!(
+                labeled_metrics.iter().any(|name| name == metric_name),
+                "{}",
+                metric_name
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    /// Locations that aren't up but still report a value should keep reporting it unless asked
+    /// to report NaN instead.
+    fn nan_on_non_up() -> Result<()> {
+        let data = parse_current_status(include_str!("../tests/data/trouble_monitor.json"))?;
+        let london = ["URL", "test", "01", "", "London - UK", "RESPONSETIME"];
+        let bucharest = ["URL", "test", "01", "", "Bucharest - RO", "RESPONSETIME"];
+
+        let metrics = Metrics::new(&MetricsSettings::default())?;
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        let latency = |labels| {
+            metrics
+                .monitor_latency_seconds
+                .with_label_values(labels)
+                .get()
+        };
+        assert_eq!(latency(&london), 0.421);
+        assert_eq!(latency(&bucharest), 0.757);
+
+        let metrics = Metrics::new(&MetricsSettings::default())?;
+        let options = MetricsOptions {
+            nan_on_non_up: true,
+            ..Default::default()
+        };
+        update_metrics_from_current_status(&metrics, &data, &options);
+        let latency = |labels| {
+            metrics
+                .monitor_latency_seconds
+                .with_label_values(labels)
+                .get()
+        };
+        assert_eq!(latency(&london), 0.421);
+        assert!(latency(&bucharest).is_nan());
+        assert_eq!(
+            metrics
+                .monitor_latency_seconds_histogram
+                .with_label_values(&["URL"])
+                .get_sample_count(),
+            1
+        );
+        Ok(())
+    }
+
+    #[test]
+    /// Locations that aren't up should be counted per monitor and the count should be cleaned up
+    /// along with the monitor.
+    fn down_locations() -> Result<()> {
+        let metrics = Metrics::new(&MetricsSettings::default())?;
+        let data = parse_current_status(include_str!("../tests/data/down_monitor.json"))?;
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        assert_eq!(
+            metrics
+                .monitor_down_locations
+                .with_label_values(&["URL", "test", "01", ""])
+                .get(),
+            1
+        );
+
+        let data = parse_current_status(include_str!("../tests/data/simple_two_monitors.json"))?;
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        let metric_families = metrics.registry.gather();
+        assert!(!has_label_with_value(
+            &metric_families,
+            "site24x7_monitor_down_locations",
+            "monitor_name",
+            "test"
+        ));
+        assert_eq!(
+            metrics
+                .monitor_down_locations
+                .with_label_values(&["URL", "test1", "01", ""])
+                .get(),
+            0
+        );
+        Ok(())
+    }
+
+    #[test]
+    /// Every monitor should tell from how many locations it's checked.
+    fn locations_total() -> Result<()> {
+        let metrics = Metrics::new(&MetricsSettings::default())?;
+        let data = parse_current_status(include_str!("../tests/data/simple_two_locations.json"))?;
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        assert_eq!(
+            metrics
+                .monitor_locations_total
+                .with_label_values(&["URL", "test", "01", ""])
+                .get(),
+            2
+        );
+        Ok(())
+    }
+
+    #[test]
+    /// A location reported twice should only be exported once with its most recent data no matter
+    /// in which order the two occurrences come.
+    fn duplicate_location() -> Result<()> {
+        let metrics = Metrics::new(&MetricsSettings::default())?;
+        let data = parse_current_status(include_str!("../tests/data/duplicate_location.json"))?;
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        let metric_families = metrics.registry.gather();
+        let latencies = metric_families
+            .iter()
+            .find(|mf| mf.get_name() == "site24x7_monitor_latency_seconds")
+            .unwrap()
+            .get_metric();
+        assert_eq!(latencies.len(), 2);
+        assert_eq!(
+            metrics
+                .monitor_latency_seconds
+                .with_label_values(&["URL", "test", "01", "", "London - UK", "RESPONSETIME"])
+                .get(),
+            0.421
+        );
+        assert_eq!(
+            metrics
+                .monitor_up
+                .with_label_values(&["URL", "test", "01", "", "London - UK"])
+                .get(),
+            1
+        );
+        assert_eq!(
+            metrics
+                .monitor_down_locations
+                .with_label_values(&["URL", "test", "01", ""])
+                .get(),
+            0
+        );
+        Ok(())
+    }
+
+    #[test]
+    /// Only the current status of a location should be set in the status stateset.
+    fn status_info() -> Result<()> {
+        let metrics = Metrics::new(&MetricsSettings::default())?;
+        let data = parse_current_status(include_str!("../tests/data/critical_monitor.json"))?;
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        let status_info = |location, status| {
+            metrics
+                .monitor_status_info
+                .with_label_values(&["URL", "test", "01", "", location, status])
+                .get()
+        };
+        assert_eq!(status_info("Bucharest - RO", "Critical"), 1);
+        assert_eq!(status_info("Bucharest - RO", "Up"), 0);
+        assert_eq!(status_info("London - UK", "Critical"), 0);
+        assert_eq!(status_info("London - UK", "Up"), 1);
+
+        let metric_families = metrics.registry.gather();
+        let series = metric_families
+            .iter()
+            .find(|mf| mf.get_name() == "site24x7_monitor_status_info")
+            .unwrap()
+            .get_metric();
+        assert_eq!(series.len(), 2 * site24x7_types::Status::iter().count());
+        assert_eq!(
+            series
+                .iter()
+                .map(|m| m.get_gauge().get_value())
+                .sum::<f64>(),
+            2.0
+        );
+        Ok(())
+    }
+
+    #[test]
+    /// Locations in maintenance should be flagged as such but not as suspended.
+    fn maintenance_monitor() -> Result<()> {
+        let metrics = Metrics::new(&MetricsSettings::default())?;
+        let data = parse_current_status(include_str!("../tests/data/maintenance_monitor.json"))?;
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        let london = ["URL", "test", "01", "", "London - UK"];
+        let bucharest = ["URL", "test", "01", "", "Bucharest - RO"];
+        assert_eq!(
+            metrics.monitor_maintenance.with_label_values(&london).get(),
+            0
+        );
+        assert_eq!(
+            metrics
+                .monitor_maintenance
+                .with_label_values(&bucharest)
+                .get(),
+            1
+        );
+        assert_eq!(
+            metrics.monitor_suspended.with_label_values(&london).get(),
+            0
+        );
+        assert_eq!(
+            metrics
+                .monitor_suspended
+                .with_label_values(&bucharest)
+                .get(),
+            0
+        );
+        assert_eq!(metrics.monitor_up.with_label_values(&bucharest).get(), 7);
+
+        // The flags should be reset once maintenance is over and cleaned up along with the
+        // location.
+        let data = parse_current_status(include_str!("../tests/data/simple_one_location.json"))?;
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        assert_eq!(
+            metrics
+                .monitor_maintenance
+                .with_label_values(&bucharest)
+                .get(),
+            0
+        );
+        let metric_families = metrics.registry.gather();
+        assert!(!has_label_with_value(
+            &metric_families,
+            "site24x7_monitor_maintenance",
+            "location",
+            "London - UK"
+        ));
+
+        Ok(())
+    }
+
     #[test]
     /// Monitors that are down will overwrite previous values with +Inf.
     fn down_monitor_updates_previous_value_to_inf() -> Result<()> {
-        clear_state();
+        let metrics = Metrics::new(&MetricsSettings::default())?;
         let before = parse_current_status(include_str!("../tests/data/simple_two_locations.json"))?;
         let after = parse_current_status(include_str!("../tests/data/down_monitor.json"))?;
-        update_metrics_from_current_status(&before);
+        update_metrics_from_current_status(&metrics, &before, &MetricsOptions::default());
         assert_eq!(
-            MONITOR_LATENCY_SECONDS_GAUGE
-                .with_label_values(&["URL", "test", "", "London - UK"])
+            metrics
+                .monitor_latency_seconds
+                .with_label_values(&["URL", "test", "01", "", "London - UK", "RESPONSETIME"])
                 .get(),
             0.421
         );
         assert_eq!(
-            MONITOR_LATENCY_SECONDS_GAUGE
-                .with_label_values(&["URL", "test", "", "Bucharest - RO"])
+            metrics
+                .monitor_latency_seconds
+                .with_label_values(&["URL", "test", "01", "", "Bucharest - RO", "RESPONSETIME"])
                 .get(),
             0.757
         );
-        update_metrics_from_current_status(&after);
+        update_metrics_from_current_status(&metrics, &after, &MetricsOptions::default());
         assert_eq!(
-            MONITOR_LATENCY_SECONDS_GAUGE
-                .with_label_values(&["URL", "test", "", "London - UK"])
+            metrics
+                .monitor_latency_seconds
+                .with_label_values(&["URL", "test", "01", "", "London - UK", "RESPONSETIME"])
                 .get(),
             27.458
         );
-        assert!(MONITOR_LATENCY_SECONDS_GAUGE
-            .with_label_values(&["URL", "test", "", "Bucharest - RO"])
+        assert!(metrics
+            .monitor_latency_seconds
+            .with_label_values(&["URL", "test", "01", "", "Bucharest - RO", "RESPONSETIME"])
             .get()
             .is_infinite());
 
         Ok(())
     }
 
+    #[test]
+    /// Monitors should be counted by type across all groups.
+    fn monitor_and_group_totals() -> Result<()> {
+        let metrics = Metrics::new(&MetricsSettings::default())?;
+        let data = parse_current_status(include_str!("../tests/data/full.json"))?;
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        assert_eq!(metrics.monitors_total.with_label_values(&["URL"]).get(), 2);
+        assert_eq!(
+            metrics
+                .monitors_total
+                .with_label_values(&["HOMEPAGE"])
+                .get(),
+            2
+        );
+        assert_eq!(
+            metrics
+                .monitors_total
+                .with_label_values(&["REALBROWSER"])
+                .get(),
+            1
+        );
+        assert_eq!(metrics.monitor_groups_total.get(), 2);
+
+        // Monitor types we don't know are still counted.
+        let data = parse_current_status(
+            r#"{"data": {"monitors": [{"monitor_type": "SSL_CERT"}, {"monitor_type": "DNS"}]}}"#,
+        )?;
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
+        let metric_families = metrics.registry.gather();
+        assert!(!has_label_with_value(
+            &metric_families,
+            "site24x7_monitors_total",
+            "monitor_type",
+            "URL"
+        ));
+        assert_eq!(
+            metrics.monitors_total.with_label_values(&["Unknown"]).get(),
+            2
+        );
+        assert_eq!(metrics.monitor_groups_total.get(), 0);
+        Ok(())
+    }
+
+    #[test]
+    /// Groups containing monitors of unexpected types should report the number of offenders.
+    fn group_type_violations() -> Result<()> {
+        let metrics = Metrics::new(&MetricsSettings::default())?;
+        let data = parse_current_status(include_str!("../tests/data/full.json"))?;
+        let mut options = MetricsOptions::default();
+        options
+            .group_expected_types
+            .insert("production".to_string(), HashSet::from(["URL".to_string()]));
+        options.group_expected_types.insert(
+            "integration".to_string(),
+            HashSet::from(["HOMEPAGE".to_string()]),
+        );
+        update_metrics_from_current_status(&metrics, &data, &options);
+        assert_eq!(
+            metrics
+                .group_type_violation
+                .with_label_values(&["production"])
+                .get(),
+            2
+        );
+        assert_eq!(
+            metrics
+                .group_type_violation
+                .with_label_values(&["integration"])
+                .get(),
+            0
+        );
+        Ok(())
+    }
+
+    #[test]
+    /// The oldest data age should be derived from the oldest poll time across all monitors.
+    fn oldest_data_age() -> Result<()> {
+        let data = parse_current_status(include_str!("../tests/data/full.json"))?;
+        let now = DateTime::parse_from_rfc3339("2021-01-06T18:44:10Z")?.with_timezone(&Utc);
+        assert_eq!(oldest_data_age_seconds(&data, now), Some(3600.0));
+
+        let data = parse_current_status(include_str!("../tests/data/empty_response.json"))?;
+        assert_eq!(oldest_data_age_seconds(&data, now), None);
+        Ok(())
+    }
+
     #[test]
     /// Check that there are no changes between two identical status updates.
     fn identical_update_no_changes() -> Result<()> {
-        clear_state();
+        let metrics = Metrics::new(&MetricsSettings::default())?;
         let s = include_str!("../tests/data/full.json");
         let data = parse_current_status(s)?;
-        update_metrics_from_current_status(&data);
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
         let mut before = vec![];
         let encoder = TextEncoder::new();
-        encoder.encode(&prometheus::gather(), &mut before).unwrap();
-        update_metrics_from_current_status(&data);
+        encoder
+            .encode(&gather_without_time_dependent(&metrics), &mut before)
+            .unwrap();
+        update_metrics_from_current_status(&metrics, &data, &MetricsOptions::default());
         let mut after = vec![];
         let encoder = TextEncoder::new();
-        encoder.encode(&prometheus::gather(), &mut after).unwrap();
+        encoder
+            .encode(&gather_without_time_dependent(&metrics), &mut after)
+            .unwrap();
         assert_eq!(before, after);
         Ok(())
     }
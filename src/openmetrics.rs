@@ -0,0 +1,241 @@
+//! Module containing an encoder for the OpenMetrics text format.
+//!
+//! The `prometheus` crate only knows about the classic Prometheus text format which differs in a
+//! few details, most notably in how counters are named and that the output has to be terminated.
+//! See https://github.com/OpenObservability/OpenMetrics/blob/main/specification/OpenMetrics.md
+use std::io::Write;
+
+use prometheus::proto::{LabelPair, Metric, MetricFamily, MetricType};
+use prometheus::Encoder;
+
+/// Content type of the OpenMetrics text format.
+pub const OPENMETRICS_FORMAT: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Line that has to terminate the OpenMetrics text format.
+pub const OPENMETRICS_EOF: &str = "# EOF\n";
+
+/// Encoder for the OpenMetrics text format.
+#[derive(Debug, Default)]
+pub struct OpenMetricsEncoder;
+
+impl OpenMetricsEncoder {
+    pub fn new() -> Self {
+        OpenMetricsEncoder
+    }
+
+    /// Encode `metric_families` without terminating the output so that more may follow.
+    pub fn encode_unterminated<W: Write>(
+        &self,
+        metric_families: &[MetricFamily],
+        writer: &mut W,
+    ) -> prometheus::Result<()> {
+        for mf in metric_families {
+            let name = mf.get_name();
+            let (type_name, family_name) = match mf.get_field_type() {
+                // Counter samples carry the `_total` suffix but their family doesn't.
+                MetricType::COUNTER => ("counter", name.strip_suffix("_total").unwrap_or(name)),
+                MetricType::GAUGE => ("gauge", name),
+                MetricType::HISTOGRAM => ("histogram", name),
+                MetricType::SUMMARY => ("summary", name),
+                MetricType::UNTYPED => ("unknown", name),
+            };
+            writeln!(
+                writer,
+                "# HELP {} {}",
+                family_name,
+                escape_help(mf.get_help())
+            )?;
+            writeln!(writer, "# TYPE {family_name} {type_name}")?;
+
+            for m in mf.get_metric() {
+                match mf.get_field_type() {
+                    MetricType::COUNTER => write_sample(
+                        writer,
+                        &format!("{family_name}_total"),
+                        m,
+                        None,
+                        m.get_counter().get_value(),
+                    )?,
+                    MetricType::GAUGE => {
+                        write_sample(writer, name, m, None, m.get_gauge().get_value())?
+                    }
+                    // We never create untyped metrics but we'll still need to handle them.
+                    #[allow(deprecated)]
+                    MetricType::UNTYPED => {
+                        write_sample(writer, name, m, None, m.get_untyped().get_value())?
+                    }
+                    MetricType::HISTOGRAM => {
+                        let h = m.get_histogram();
+                        let bucket_name = format!("{name}_bucket");
+                        let mut has_inf_bucket = false;
+                        for b in h.get_bucket() {
+                            let upper_bound = b.get_upper_bound();
+                            has_inf_bucket |= upper_bound == f64::INFINITY;
+                            write_sample(
+                                writer,
+                                &bucket_name,
+                                m,
+                                Some(("le", &format_float(upper_bound))),
+                                b.get_cumulative_count() as f64,
+                            )?;
+                        }
+                        if !has_inf_bucket {
+                            write_sample(
+                                writer,
+                                &bucket_name,
+                                m,
+                                Some(("le", "+Inf")),
+                                h.get_sample_count() as f64,
+                            )?;
+                        }
+                        write_sample(
+                            writer,
+                            &format!("{name}_count"),
+                            m,
+                            None,
+                            h.get_sample_count() as f64,
+                        )?;
+                        write_sample(writer, &format!("{name}_sum"), m, None, h.get_sample_sum())?;
+                    }
+                    MetricType::SUMMARY => {
+                        let s = m.get_summary();
+                        for q in s.get_quantile() {
+                            write_sample(
+                                writer,
+                                name,
+                                m,
+                                Some(("quantile", &format_float(q.get_quantile()))),
+                                q.get_value(),
+                            )?;
+                        }
+                        write_sample(
+                            writer,
+                            &format!("{name}_count"),
+                            m,
+                            None,
+                            s.get_sample_count() as f64,
+                        )?;
+                        write_sample(writer, &format!("{name}_sum"), m, None, s.get_sample_sum())?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Encoder for OpenMetricsEncoder {
+    fn encode<W: Write>(
+        &self,
+        metric_families: &[MetricFamily],
+        writer: &mut W,
+    ) -> prometheus::Result<()> {
+        self.encode_unterminated(metric_families, writer)?;
+        writer.write_all(OPENMETRICS_EOF.as_bytes())?;
+        Ok(())
+    }
+
+    fn format_type(&self) -> &str {
+        OPENMETRICS_FORMAT
+    }
+}
+
+/// Write a single sample line of `metric` with an optional `extra_label`.
+fn write_sample<W: Write>(
+    writer: &mut W,
+    name: &str,
+    metric: &Metric,
+    extra_label: Option<(&str, &str)>,
+    value: f64,
+) -> prometheus::Result<()> {
+    write!(writer, "{name}")?;
+    let labels = metric
+        .get_label()
+        .iter()
+        .map(|l: &LabelPair| (l.get_name(), l.get_value()))
+        .chain(extra_label);
+    let mut separator = "{";
+    for (label_name, label_value) in labels {
+        write!(
+            writer,
+            "{separator}{label_name}=\"{}\"",
+            escape_label_value(label_value)
+        )?;
+        separator = ",";
+    }
+    if separator == "," {
+        write!(writer, "}}")?;
+    }
+    write!(writer, " {}", format_float(value))?;
+    // OpenMetrics timestamps are in seconds rather than milliseconds.
+    if metric.get_timestamp_ms() != 0 {
+        write!(
+            writer,
+            " {}",
+            format_float(metric.get_timestamp_ms() as f64 / 1000.0)
+        )?;
+    }
+    writeln!(writer)?;
+    Ok(())
+}
+
+fn format_float(v: f64) -> String {
+    if v == f64::INFINITY {
+        "+Inf".to_string()
+    } else if v == f64::NEG_INFINITY {
+        "-Inf".to_string()
+    } else if v.is_nan() {
+        "NaN".to_string()
+    } else {
+        v.to_string()
+    }
+}
+
+fn escape_help(help: &str) -> String {
+    help.replace('\\', r"\\").replace('\n', r"\n")
+}
+
+fn escape_label_value(value: &str) -> String {
+    escape_help(value).replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use prometheus::{GaugeVec, IntCounter, Opts, Registry};
+
+    use super::*;
+
+    #[test]
+    /// Counter families shouldn't carry the `_total` suffix while their samples do and the
+    /// output must be terminated.
+    fn counters_and_gauges() -> prometheus::Result<()> {
+        let registry = Registry::new();
+        let counter = IntCounter::new("requests_total", "Number of requests.")?;
+        counter.inc();
+        registry.register(Box::new(counter))?;
+        let gauge = GaugeVec::new(
+            Opts::new("latency_seconds", "Latency with a \"quoted\"\nhelp."),
+            &["location"],
+        )?;
+        gauge
+            .with_label_values(&["London \"UK\""])
+            .set(f64::INFINITY);
+        registry.register(Box::new(gauge))?;
+
+        let mut buffer = vec![];
+        OpenMetricsEncoder::new().encode(&registry.gather(), &mut buffer)?;
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            r#"# HELP latency_seconds Latency with a "quoted"\nhelp.
+# TYPE latency_seconds gauge
+latency_seconds{location="London \"UK\""} +Inf
+# HELP requests Number of requests.
+# TYPE requests counter
+requests_total 1
+# EOF
+"#
+        );
+        Ok(())
+    }
+}
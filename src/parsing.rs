@@ -1,6 +1,7 @@
 //! Module containing functions related to parsing the Site24x7 API payload.
 use anyhow::{anyhow, Context, Result};
 use log::debug;
+use serde_path_to_error::Segment;
 
 use crate::site24x7_types as types;
 
@@ -8,10 +9,23 @@ use crate::site24x7_types as types;
 pub fn parse_current_status(
     json: &str,
 ) -> Result<types::CurrentStatusData, types::CurrentStatusError> {
+    parse_current_status_page(json).map(|(data, _)| data)
+}
+
+/// Parse a single page of current status along with where it is among all pages if the data is
+/// paginated.
+pub fn parse_current_status_page(
+    json: &str,
+) -> Result<(types::CurrentStatusData, Option<types::PageInfo>), types::CurrentStatusError> {
     let deserializer = &mut serde_json::Deserializer::from_str(json);
     let current_status_resp_result = serde_path_to_error::deserialize(deserializer);
 
-    let v: serde_json::Value = serde_json::from_str(json).context("JSON seems invalid.")?;
+    // Gateways in front of the API like to respond with HTML error pages. There's no point in
+    // logging those in full unless we're debugging.
+    let v: serde_json::Value = serde_json::from_str(json).map_err(|e| {
+        debug!("Response from server isn't JSON: \n{}", json);
+        types::CurrentStatusError::ParseError(format!("Response isn't JSON: {e}"))
+    })?;
     debug!(
         "JSON received from server: \n{}",
         serde_json::to_string_pretty(&v).context("Couldn't format JSON for debug output")?
@@ -23,16 +37,18 @@ pub fn parse_current_status(
             let debug_deserializer = &mut serde_json::Deserializer::from_str(json);
             let debug_deserializer_result: Result<types::CurrentStatusResponseInner, _> =
                 serde_path_to_error::deserialize(debug_deserializer);
-            let debug_err = debug_deserializer_result.err();
-            anyhow!(types::CurrentStatusError::ParseError(e.to_string()))
-                .context(debug_err.map(|e| e.to_string()).unwrap_or_default())
+            let err = anyhow!(types::CurrentStatusError::ParseError(e.to_string()));
+            match debug_deserializer_result {
+                Err(debug_err) => err.context(json_path_error(&v, &debug_err)),
+                Ok(_) => err,
+            }
         })
         .context("Couldn't parse server response while fetching monitors.".to_string())?;
 
     match current_status_resp_parsed {
-        types::CurrentStatusResponse::Success(inner) => Ok(inner.data),
+        types::CurrentStatusResponse::Success(inner) => Ok((inner.data, inner.page_info)),
         types::CurrentStatusResponse::Error(e) => {
-            if e.message == "OAuth Access Token is invalid or has expired." {
+            if e.is_auth_error() {
                 Err(types::CurrentStatusError::ApiAuthError(e.message))
             } else {
                 Err(types::CurrentStatusError::ApiUnknownError(e.message))
@@ -41,6 +57,38 @@ pub fn parse_current_status(
     }
 }
 
+/// Turn `e` which happened while deserializing `v` into an error with the full path to the
+/// offending field.
+///
+/// Monitors are deserialized from an intermediate `Value` which loses track of the path within
+/// them. In that case we'll deserialize the offending monitor on its own once more to find out
+/// which of its fields is to blame.
+fn json_path_error(
+    v: &serde_json::Value,
+    e: &serde_path_to_error::Error<serde_json::Error>,
+) -> types::JsonPathError {
+    let mut value = Some(v);
+    for segment in e.path() {
+        value = match segment {
+            Segment::Seq { index } => value.and_then(|v| v.get(index)),
+            Segment::Map { key } => value.and_then(|v| v.get(key)),
+            Segment::Enum { .. } | Segment::Unknown => None,
+        };
+    }
+    if let Some(monitor) = value.filter(|v| v.get("monitor_type").is_some()) {
+        if let Err(monitor_err) = serde_path_to_error::deserialize::<_, types::Monitor>(monitor) {
+            return types::JsonPathError {
+                path: format!("{}.{}", e.path(), monitor_err.path()),
+                message: monitor_err.inner().to_string(),
+            };
+        }
+    }
+    types::JsonPathError {
+        path: e.path().to_string(),
+        message: e.inner().to_string(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use chrono::DateTime;
@@ -58,6 +106,133 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    /// A response that isn't JSON at all should be reported as a parse error without its body.
+    fn non_json_is_parse_error() {
+        let err = parse_current_status("<html>Bad Gateway</html>").unwrap_err();
+        assert!(matches!(err, types::CurrentStatusError::ParseError(_)));
+        assert_eq!(err.kind(), "parse");
+        assert!(!err.to_string().contains("Bad Gateway"));
+    }
+
+    #[test]
+    /// Data that doesn't match the expected structure should be reported as a parse error.
+    fn invalid_structure_is_parse_error() {
+        let s = r#"{"data": {"monitors": [{"monitor_type": "URL"}]}}"#;
+        let err = parse_current_status(s).unwrap_err();
+        assert_eq!(err.kind(), "parse");
+    }
+
+    #[test]
+    /// The path to a field that couldn't be parsed should be known, even inside of monitors.
+    fn malformed_status_json_path() {
+        let s = include_str!("../tests/data/malformed_status.json");
+        let err = parse_current_status(s).unwrap_err();
+        assert_eq!(err.kind(), "parse");
+        assert_eq!(err.json_path(), Some("data.monitors[2].status"));
+
+        let err = parse_current_status(r#"{"data": {"monitors": {}}}"#).unwrap_err();
+        assert_eq!(err.json_path(), Some("data.monitors"));
+
+        let err = parse_current_status("<html>Bad Gateway</html>").unwrap_err();
+        assert_eq!(err.json_path(), None);
+    }
+
+    #[test]
+    /// Errors should be told apart by their code rather than their wording.
+    fn api_error_codes() {
+        let s = r#"{"error_code": 1030, "message": "Access token expired", "error_info": {}}"#;
+        let err = parse_current_status(s).unwrap_err();
+        assert!(matches!(err, types::CurrentStatusError::ApiAuthError(_)));
+        assert_eq!(err.kind(), "auth");
+
+        let s = r#"{"error_code": 1001, "message": "Invalid data", "error_info": {}}"#;
+        let err = parse_current_status(s).unwrap_err();
+        assert!(matches!(err, types::CurrentStatusError::ApiUnknownError(_)));
+        assert_eq!(err.kind(), "unknown");
+    }
+
+    #[test]
+    /// Attribute values may be integers, fractional or strings with "-" meaning there is no value.
+    fn attribute_value_formats() -> Result<()> {
+        for (attribute_value, expected) in [
+            ("123", Some(123.0)),
+            ("123.45", Some(123.45)),
+            (r#""123""#, Some(123.0)),
+            (r#""-""#, None),
+            ("null", None),
+        ] {
+            let location: types::Location = serde_json::from_str(&format!(
+                r#"{{"location_name": "London - UK", "attribute_value": {attribute_value}}}"#
+            ))?;
+            assert_eq!(location.attribute_value, expected, "{attribute_value}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    /// Some monitor types have their `attribute_value` quoted which shouldn't make us lose it.
+    fn string_attribute_value() -> Result<()> {
+        let s = include_str!("../tests/data/string_attribute_value.json");
+        let data = parse_current_status(s)?;
+        let monitor = data.monitors[0].monitor().unwrap();
+        assert_eq!(monitor.attribute_value, Some(173.0));
+        assert_eq!(monitor.locations[0].attribute_value, Some(173.0));
+        assert_eq!(monitor.locations[1].attribute_value, None);
+        Ok(())
+    }
+
+    #[test]
+    /// PORT monitors should be parsed just like URL monitors.
+    fn port_monitor() -> Result<()> {
+        let s = include_str!("../tests/data/port_monitor.json");
+        let data = parse_current_status(s)?;
+        let types::MonitorMaybe::PORT(monitor) = &data.monitors[0] else {
+            panic!("Expected a PORT monitor but got {:?}", data.monitors[0]);
+        };
+        assert_eq!(data.monitors[0].to_string(), "PORT");
+        assert_eq!(monitor.name, "test");
+        assert_eq!(monitor.unit.as_deref(), Some("ms"));
+        assert_eq!(monitor.locations[0].attribute_value, Some(32.0));
+        Ok(())
+    }
+
+    #[test]
+    /// The steps of REALBROWSER transactions should be parsed if there are any.
+    fn realbrowser_steps() -> Result<()> {
+        let s = include_str!("../tests/data/realbrowser_steps.json");
+        let data = parse_current_status(s)?;
+        let types::MonitorMaybe::REALBROWSER(monitor) = &data.monitors[0] else {
+            panic!(
+                "Expected a REALBROWSER monitor but got {:?}",
+                data.monitors[0]
+            );
+        };
+        assert_eq!(
+            monitor.steps,
+            vec![
+                types::Step {
+                    step_name: "Open homepage".to_string(),
+                    attribute_value: Some(1200.0),
+                },
+                types::Step {
+                    step_name: "Log in".to_string(),
+                    attribute_value: Some(3121.0),
+                },
+                types::Step {
+                    step_name: "Check out".to_string(),
+                    attribute_value: None,
+                },
+            ]
+        );
+
+        let s = include_str!("../tests/data/full.json");
+        let data = parse_current_status(s)?;
+        let monitor = data.monitor_groups[0].monitors[0].monitor().unwrap();
+        assert!(monitor.steps.is_empty());
+        Ok(())
+    }
+
     #[test]
     /// Properly handle empty lists for monitors.
     fn empty_lists() -> Result<()> {
@@ -89,7 +264,7 @@ mod tests {
                 {
                     types::Location {
                         status: types::Status::Up,
-                        attribute_value: Some(757),
+                        attribute_value: Some(757.0),
                         location_name: "Bucharest - RO".to_string(),
                         last_polled_time: Some(DateTime::parse_from_str(
                             "2021-01-06T18:53:06+0000",
@@ -102,6 +277,7 @@ mod tests {
             attribute_value: None,
             monitor_id: "01".to_string(),
             tags: vec![],
+            steps: vec![],
             last_polled_time: Some(DateTime::parse_from_str(
                 "2021-01-06T18:53:07+0000",
                 types::DATE_FORMAT,
@@ -140,7 +316,7 @@ mod tests {
                 {
                     types::Location {
                         status: types::Status::Up,
-                        attribute_value: Some(757),
+                        attribute_value: Some(757.0),
                         location_name: "Bucharest - RO".to_string(),
                         last_polled_time: Some(DateTime::parse_from_str(
                             "2021-01-06T18:53:06+0000",
@@ -153,6 +329,7 @@ mod tests {
             attribute_value: None,
             monitor_id: "01".to_string(),
             tags: vec![],
+            steps: vec![],
             last_polled_time: Some(DateTime::parse_from_str(
                 "2021-01-06T18:53:07+0000",
                 types::DATE_FORMAT,
@@ -172,6 +349,8 @@ mod tests {
         let expected_monitor_group_prod = types::MonitorGroup {
             group_id: "01".to_string(),
             group_name: "production".to_string(),
+            status: Some(types::Status::Down),
+            subgroups: vec![],
             monitors: vec![
                 types::MonitorMaybe::REALBROWSER(types::Monitor {
                     name: "production (realbrowser)".to_string(),
@@ -181,7 +360,7 @@ mod tests {
                     locations: vec![
                         types::Location {
                             status: types::Status::Up,
-                            attribute_value: Some(27458),
+                            attribute_value: Some(27458.0),
                             location_name: "Falkenstein - DE".to_string(),
                             last_polled_time: Some(DateTime::parse_from_str(
                                 "2021-01-06T18:27:41+0000",
@@ -199,9 +378,10 @@ mod tests {
                         },
                     ],
                     attribute_name: "TRANSACTIONTIME".to_string(),
-                    attribute_value: Some(27458),
+                    attribute_value: Some(27458.0),
                     monitor_id: "0101".to_string(),
                     tags: vec![],
+                    steps: vec![],
                     last_polled_time: Some(DateTime::parse_from_str(
                         "2021-01-06T18:27:41+0000",
                         types::DATE_FORMAT,
@@ -215,7 +395,7 @@ mod tests {
                     locations: vec![
                         types::Location {
                             status: types::Status::Up,
-                            attribute_value: Some(718),
+                            attribute_value: Some(718.0),
                             location_name: "Falkenstein - DE".to_string(),
                             last_polled_time: Some(DateTime::parse_from_str(
                                 "2021-01-06T17:44:10+0000",
@@ -224,7 +404,7 @@ mod tests {
                         },
                         types::Location {
                             status: types::Status::Up,
-                            attribute_value: Some(3830),
+                            attribute_value: Some(3830.0),
                             location_name: "Shenzhen - CHN".to_string(),
                             last_polled_time: Some(DateTime::parse_from_str(
                                 "2021-01-06T17:44:10+0000",
@@ -233,9 +413,10 @@ mod tests {
                         },
                     ],
                     attribute_name: "RESPONSETIME".to_string(),
-                    attribute_value: Some(718),
+                    attribute_value: Some(718.0),
                     monitor_id: "0102".to_string(),
                     tags: vec![],
+                    steps: vec![],
                     last_polled_time: Some(DateTime::parse_from_str(
                         "2021-01-06T17:44:10+0000",
                         types::DATE_FORMAT,
@@ -249,7 +430,7 @@ mod tests {
                     locations: vec![
                         types::Location {
                             status: types::Status::Up,
-                            attribute_value: Some(173),
+                            attribute_value: Some(173.0),
                             location_name: "Falkenstein - DE".to_string(),
                             last_polled_time: Some(DateTime::parse_from_str(
                                 "2021-01-06T18:43:27+0000",
@@ -258,7 +439,7 @@ mod tests {
                         },
                         types::Location {
                             status: types::Status::Up,
-                            attribute_value: Some(2322),
+                            attribute_value: Some(2322.0),
                             location_name: "Shenzhen - CHN".to_string(),
                             last_polled_time: Some(DateTime::parse_from_str(
                                 "2021-01-06T18:42:16+0000",
@@ -267,9 +448,10 @@ mod tests {
                         },
                     ],
                     attribute_name: "RESPONSETIME".to_string(),
-                    attribute_value: Some(173),
+                    attribute_value: Some(173.0),
                     monitor_id: "0103".to_string(),
                     tags: vec![],
+                    steps: vec![],
                     last_polled_time: Some(DateTime::parse_from_str(
                         "2021-01-06T18:43:27+0000",
                         types::DATE_FORMAT,
@@ -280,6 +462,8 @@ mod tests {
         let expected_monitor_group_int = types::MonitorGroup {
             group_id: "02".to_string(),
             group_name: "integration".to_string(),
+            status: Some(types::Status::Up),
+            subgroups: vec![],
             monitors: vec![types::MonitorMaybe::HOMEPAGE(types::Monitor {
                 name: "integration (homepage)".to_string(),
                 unit: Some("ms".to_string()),
@@ -288,7 +472,7 @@ mod tests {
                 locations: vec![
                     types::Location {
                         status: types::Status::Up,
-                        attribute_value: Some(1081),
+                        attribute_value: Some(1081.0),
                         location_name: "Falkenstein - DE".to_string(),
                         last_polled_time: Some(DateTime::parse_from_str(
                             "2021-01-06T18:33:34+0000",
@@ -297,7 +481,7 @@ mod tests {
                     },
                     types::Location {
                         status: types::Status::Up,
-                        attribute_value: Some(13706),
+                        attribute_value: Some(13706.0),
                         location_name: "Shenzhen - CHN".to_string(),
                         last_polled_time: Some(DateTime::parse_from_str(
                             "2021-01-06T18:18:31+0000",
@@ -306,7 +490,7 @@ mod tests {
                     },
                 ],
                 attribute_name: "RESPONSETIME".to_string(),
-                attribute_value: Some(1081),
+                attribute_value: Some(1081.0),
                 monitor_id: "0201".to_string(),
                 tags: vec![
                     types::Tag {
@@ -322,6 +506,7 @@ mod tests {
                         value: "test3v:a:b".to_string(),
                     },
                 ],
+                steps: vec![],
                 last_polled_time: Some(DateTime::parse_from_str(
                     "2021-01-06T18:33:34+0000",
                     types::DATE_FORMAT,
@@ -337,7 +522,7 @@ mod tests {
                 {
                     types::Location {
                         status: types::Status::Up,
-                        attribute_value: Some(1534),
+                        attribute_value: Some(1534.0),
                         location_name: "Singapore - SG".to_string(),
                         last_polled_time: Some(DateTime::parse_from_str(
                             "2021-01-06T18:26:31+0000",
@@ -347,7 +532,7 @@ mod tests {
                 },
                 types::Location {
                     status: types::Status::Up,
-                    attribute_value: Some(165),
+                    attribute_value: Some(165.0),
                     location_name: "London - UK".to_string(),
                     last_polled_time: Some(DateTime::parse_from_str(
                         "2021-01-06T18:26:31+0000",
@@ -356,9 +541,10 @@ mod tests {
                 },
             ],
             attribute_name: "RESPONSETIME".to_string(),
-            attribute_value: Some(139),
+            attribute_value: Some(139.0),
             monitor_id: "00".to_string(),
             tags: vec![],
+            steps: vec![],
             last_polled_time: Some(DateTime::parse_from_str(
                 "2021-01-06T18:41:53+0000",
                 types::DATE_FORMAT,
@@ -1,15 +1,19 @@
 //! Module containing Site24x7 API-specific types.
-use chrono::{DateTime, FixedOffset};
+use chrono::{DateTime, FixedOffset, Utc};
 use serde::{Deserialize, Deserializer};
 use serde_repr::Deserialize_repr;
-use strum_macros::Display;
+use strum_macros::{Display, EnumIter};
 use thiserror::Error;
 
+use std::time::Duration;
+
 pub static DATE_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.f%z";
 
 #[derive(Clone, Deserialize, Debug)]
 pub struct Site24x7ClientInfo {
     pub site24x7_endpoint: String,
+    /// Version of the Site24x7 API to request responses in.
+    pub api_version: String,
     pub zoho_endpoint: String,
     pub client_id: String,
     pub client_secret: String,
@@ -24,17 +28,41 @@ pub enum CurrentStatusResponse {
 
 #[derive(Clone, Deserialize, Debug)]
 pub struct ApiError {
-    #[allow(dead_code)]
     pub error_code: u16,
     pub message: String,
 }
 
+impl ApiError {
+    /// The `error_code` Site24x7 uses for access tokens that are invalid or have expired.
+    pub const INVALID_ACCESS_TOKEN: u16 = 1030;
+
+    /// Return whether the access token was rejected.
+    ///
+    /// The message is only checked in case Site24x7 uses another code for the same thing.
+    pub fn is_auth_error(&self) -> bool {
+        self.error_code == Self::INVALID_ACCESS_TOKEN
+            || self.message == "OAuth Access Token is invalid or has expired."
+    }
+}
+
 #[derive(Clone, Deserialize, Debug)]
 pub struct CurrentStatusResponseInner {
     pub data: CurrentStatusData,
+    /// Only sent if there are too many monitors for a single response.
+    #[serde(default)]
+    pub page_info: Option<PageInfo>,
 }
 
-#[derive(Clone, Deserialize_repr, Debug, PartialEq, Eq)]
+/// Which page of the current status a response contains.
+#[derive(Clone, Copy, Deserialize, Debug, PartialEq, Eq)]
+pub struct PageInfo {
+    /// Starts at 1.
+    pub page: u32,
+    #[serde(default)]
+    pub has_next: bool,
+}
+
+#[derive(Clone, Copy, Deserialize_repr, Debug, PartialEq, Eq, Display, EnumIter)]
 #[repr(u8)]
 pub enum Status {
     Down = 0,
@@ -55,7 +83,7 @@ impl Default for Status {
     }
 }
 
-#[derive(Clone, Deserialize, Debug, PartialEq, Eq)]
+#[derive(Clone, Deserialize, Debug, PartialEq)]
 pub struct CurrentStatusData {
     #[serde(default)]
     pub monitors: Vec<MonitorMaybe>,
@@ -68,6 +96,13 @@ pub enum CurrentStatusError {
     #[error("API auth error: {0}")]
     ApiAuthError(String),
 
+    /// The API wants us to back off, optionally telling us for how long.
+    #[error("API rate limit exceeded")]
+    RateLimited(Option<Duration>),
+
+    #[error("API request timed out: {0}")]
+    ApiTimeoutError(String),
+
     #[error("Unknown API error: {0}")]
     ApiUnknownError(String),
 
@@ -78,9 +113,53 @@ pub enum CurrentStatusError {
     Other(#[from] anyhow::Error),
 }
 
+impl CurrentStatusError {
+    /// Short name of the kind of error which is suitable as a metric label.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CurrentStatusError::ApiAuthError(_) => "auth",
+            CurrentStatusError::RateLimited(_) => "rate_limited",
+            CurrentStatusError::ApiTimeoutError(_) => "timeout",
+            CurrentStatusError::ParseError(_) => "parse",
+            CurrentStatusError::ApiUnknownError(_) => "unknown",
+            // Errors might have been wrapped with some context on their way up.
+            CurrentStatusError::Other(e) => e
+                .downcast_ref::<CurrentStatusError>()
+                .map_or("unknown", |e| e.kind()),
+        }
+    }
+
+    /// JSON path of the field that couldn't be parsed if this is a parse error that knows it.
+    pub fn json_path(&self) -> Option<&str> {
+        match self {
+            CurrentStatusError::Other(e) => {
+                e.downcast_ref::<JsonPathError>().map(|e| e.path.as_str())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Where in a response parsing went wrong and why.
+#[derive(Error, Debug)]
+#[error("{path}: {message}")]
+pub struct JsonPathError {
+    /// Path to the offending field such as `data.monitors[2].status`.
+    pub path: String,
+    pub message: String,
+}
+
+/// The shapes in which Site24x7 sends an `attribute_value`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawAttributeValue {
+    Number(f64),
+    String(String),
+}
+
 // TODO Remove this soon once it's removed from clippy's default lint set again.
 #[allow(clippy::unnecessary_wraps)]
-fn from_attribute_value<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+fn from_attribute_value<'de, D>(deserializer: D) -> Result<Option<f64>, D::Error>
 where
     D: Deserializer<'de>,
 {
@@ -89,8 +168,13 @@ where
     // latency measurment is possible for a down host.
     // We'll deal with this by setting `NaN` as that's what Prometheus recommends:
     // https://prometheus.io/docs/practices/instrumentation/#avoid-missing-metrics
-    let v: Option<u64> = Deserialize::deserialize(deserializer).ok();
-    Ok(v)
+    // Values might also be fractional or be sent as a string.
+    let v: Option<RawAttributeValue> = Deserialize::deserialize(deserializer).ok().flatten();
+    Ok(match v {
+        Some(RawAttributeValue::Number(n)) => Some(n),
+        Some(RawAttributeValue::String(s)) => s.trim().parse().ok().filter(|n: &f64| n.is_finite()),
+        None => None,
+    })
 }
 
 fn from_custom_dateformat<'de, D>(
@@ -109,28 +193,86 @@ where
     Ok(None)
 }
 
-#[derive(Clone, Deserialize, Debug, PartialEq, Eq)]
+#[derive(Clone, Deserialize, Debug, PartialEq)]
 pub struct Location {
     #[serde(default)]
     pub status: Status,
     #[serde(default, deserialize_with = "from_attribute_value")]
-    pub attribute_value: Option<u64>,
+    pub attribute_value: Option<f64>,
     pub location_name: String,
     #[serde(default, deserialize_with = "from_custom_dateformat")]
     pub last_polled_time: Option<DateTime<FixedOffset>>,
 }
 
-#[derive(Clone, Deserialize, Display, Debug, PartialEq, Eq)]
-#[serde(tag = "monitor_type")]
+impl Location {
+    /// Return `last_polled_time` in UTC as locations report it with their own offsets which makes
+    /// them awkward to compare.
+    pub fn last_polled_time_utc(&self) -> Option<DateTime<Utc>> {
+        self.last_polled_time.map(|t| t.with_timezone(&Utc))
+    }
+}
+
+#[derive(Clone, Display, Debug, PartialEq)]
 #[allow(clippy::upper_case_acronyms)]
 pub enum MonitorMaybe {
     URL(Monitor),
     HOMEPAGE(Monitor),
     RESTAPI(Monitor),
     REALBROWSER(Monitor),
+    PORT(Monitor),
+    SERVER(Monitor),
     // SSL_CERT(Monitor),
-    #[serde(other)]
-    Unknown,
+    /// A type of monitor we don't know how to handle along with its `monitor_type`.
+    Unknown(String),
+}
+
+impl<'de> Deserialize<'de> for MonitorMaybe {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        // This works just like `#[serde(tag = "monitor_type")]` except that we keep the type of
+        // unknown monitors around instead of resorting to `#[serde(other)]` which would lose it.
+        let value = serde_json::Value::deserialize(deserializer)?;
+        let monitor_type = value
+            .get("monitor_type")
+            .ok_or_else(|| serde::de::Error::missing_field("monitor_type"))?
+            .as_str()
+            .ok_or_else(|| serde::de::Error::custom("monitor_type is not a string"))?
+            .to_string();
+        let monitor = || Monitor::deserialize(&value).map_err(serde::de::Error::custom);
+        Ok(match monitor_type.as_str() {
+            "URL" => MonitorMaybe::URL(monitor()?),
+            "HOMEPAGE" => MonitorMaybe::HOMEPAGE(monitor()?),
+            "RESTAPI" => MonitorMaybe::RESTAPI(monitor()?),
+            "REALBROWSER" => MonitorMaybe::REALBROWSER(monitor()?),
+            "PORT" => MonitorMaybe::PORT(monitor()?),
+            "SERVER" => MonitorMaybe::SERVER(monitor()?),
+            _ => MonitorMaybe::Unknown(monitor_type),
+        })
+    }
+}
+
+impl MonitorMaybe {
+    /// Return the inner monitor unless we don't know how to handle this type of monitor.
+    pub fn monitor(&self) -> Option<&Monitor> {
+        match self {
+            MonitorMaybe::URL(m)
+            | MonitorMaybe::HOMEPAGE(m)
+            | MonitorMaybe::RESTAPI(m)
+            | MonitorMaybe::REALBROWSER(m)
+            | MonitorMaybe::PORT(m)
+            | MonitorMaybe::SERVER(m) => Some(m),
+            MonitorMaybe::Unknown(_) => None,
+        }
+    }
+
+    /// Return whether the `attribute_value` of this monitor is a latency in milliseconds.
+    ///
+    /// Server monitors report things like CPU or memory usage instead.
+    pub fn measures_latency(&self) -> bool {
+        !matches!(self, MonitorMaybe::SERVER(_) | MonitorMaybe::Unknown(_))
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -155,7 +297,7 @@ impl<'de> Deserialize<'de> for Tag {
     }
 }
 
-#[derive(Clone, Deserialize, Debug, PartialEq, Eq)]
+#[derive(Clone, Deserialize, Debug, PartialEq)]
 pub struct Monitor {
     pub name: String,
     pub unit: Option<String>,
@@ -166,18 +308,69 @@ pub struct Monitor {
     pub attribute_name: String,
     // pub attribute_label: String,
     #[serde(default, deserialize_with = "from_attribute_value")]
-    pub attribute_value: Option<u64>,
+    pub attribute_value: Option<f64>,
     pub monitor_id: String,
     #[serde(default)]
     pub tags: Vec<Tag>,
+    /// The steps of a transaction in case of REALBROWSER monitors if Site24x7 sends them at all.
+    #[serde(default)]
+    pub steps: Vec<Step>,
     #[serde(default, deserialize_with = "from_custom_dateformat")]
     pub last_polled_time: Option<DateTime<FixedOffset>>,
 }
 
-#[derive(Clone, Deserialize, Debug, PartialEq, Eq)]
+/// A single step of a transaction.
+#[derive(Clone, Deserialize, Debug, PartialEq)]
+pub struct Step {
+    pub step_name: String,
+    /// How long the step took in milliseconds.
+    #[serde(default, deserialize_with = "from_attribute_value")]
+    pub attribute_value: Option<f64>,
+}
+
+#[derive(Clone, Deserialize, Debug, PartialEq)]
 pub struct MonitorGroup {
     #[serde(default)]
     pub monitors: Vec<MonitorMaybe>,
     pub group_id: String,
     pub group_name: String,
+    /// Overall status of the monitors in the group if Site24x7 sends it.
+    #[serde(default)]
+    pub status: Option<Status>,
+    #[serde(default)]
+    pub subgroups: Vec<MonitorGroup>,
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    /// Poll times with different offsets should end up in UTC.
+    fn last_polled_time_utc() -> Result<(), chrono::ParseError> {
+        let location = |last_polled_time| Location {
+            status: Status::Up,
+            attribute_value: None,
+            location_name: "London - UK".to_string(),
+            last_polled_time,
+        };
+        let london = location(Some(DateTime::parse_from_str(
+            "2021-01-06T18:53:06+0000",
+            DATE_FORMAT,
+        )?));
+        let tokyo = location(Some(DateTime::parse_from_str(
+            "2021-01-07T03:53:06+0900",
+            DATE_FORMAT,
+        )?));
+        let expected = "2021-01-06T18:53:06+00:00";
+        assert_eq!(
+            london.last_polled_time_utc().unwrap().to_rfc3339(),
+            expected
+        );
+        assert_eq!(tokyo.last_polled_time_utc().unwrap().to_rfc3339(), expected);
+        assert_eq!(location(None).last_polled_time_utc(), None);
+        Ok(())
+    }
 }
@@ -0,0 +1,248 @@
+//! Module containing a response body that encodes metrics while it's being sent.
+//!
+//! Metric families are encoded one at a time as the client reads the body so that the memory
+//! taken by the encoded output is bounded. The gathered metric families themselves are still all
+//! in memory at once.
+use std::io::{self, Write};
+
+use flate2::{write::GzEncoder, Compression};
+use futures_util::stream;
+use hyper::body::{Body, Bytes};
+use prometheus::proto::MetricFamily;
+use prometheus::{Encoder, ProtobufEncoder, TextEncoder};
+
+use crate::influx::LineProtocolEncoder;
+use crate::openmetrics::{OpenMetricsEncoder, OPENMETRICS_EOF};
+
+/// The formats metrics can be served in.
+#[derive(Debug)]
+pub enum MetricsFormat {
+    Text,
+    OpenMetrics,
+    Protobuf,
+    /// InfluxDB line protocol with all lines at the given timestamp in nanoseconds.
+    Influx(i64),
+}
+
+impl MetricsFormat {
+    /// Content type of the encoded metrics.
+    pub fn content_type(&self) -> String {
+        match self {
+            MetricsFormat::Text => TextEncoder::new().format_type().to_string(),
+            MetricsFormat::OpenMetrics => OpenMetricsEncoder::new().format_type().to_string(),
+            MetricsFormat::Protobuf => ProtobufEncoder::new().format_type().to_string(),
+            MetricsFormat::Influx(timestamp_ns) => LineProtocolEncoder::new(*timestamp_ns)
+                .format_type()
+                .to_string(),
+        }
+    }
+
+    /// Append `metric_families` to `buffer` in such a way that more of them may follow.
+    fn encode(
+        &self,
+        metric_families: &[MetricFamily],
+        buffer: &mut Vec<u8>,
+    ) -> prometheus::Result<()> {
+        match self {
+            MetricsFormat::Text => TextEncoder::new().encode(metric_families, buffer),
+            MetricsFormat::OpenMetrics => {
+                OpenMetricsEncoder::new().encode_unterminated(metric_families, buffer)
+            }
+            MetricsFormat::Protobuf => ProtobufEncoder::new().encode(metric_families, buffer),
+            MetricsFormat::Influx(timestamp_ns) => {
+                LineProtocolEncoder::new(*timestamp_ns).encode(metric_families, buffer)
+            }
+        }
+    }
+
+    /// What has to follow the last metric family.
+    fn trailer(&self) -> &'static [u8] {
+        match self {
+            MetricsFormat::OpenMetrics => OPENMETRICS_EOF.as_bytes(),
+            _ => b"",
+        }
+    }
+}
+
+/// Encoded metrics up to this many bytes are sent all at once along with their length.
+pub const MAX_BUFFERED_SIZE: usize = 1024 * 1024;
+
+/// Return a body with `metric_families` encoded in `format`, compressing them with gzip if asked
+/// to, along with its length if it's known upfront.
+///
+/// Up to [`MAX_BUFFERED_SIZE`] bytes are encoded right away. Anything beyond that is encoded as
+/// the body is read which is why the length of such a body isn't known.
+pub fn metrics_body(
+    metric_families: Vec<MetricFamily>,
+    format: MetricsFormat,
+    gzip: bool,
+) -> (Body, Option<usize>) {
+    let mut chunks = EncodedChunks {
+        metric_families: metric_families.into_iter(),
+        format,
+        gzip: gzip.then(|| GzEncoder::new(vec![], Compression::default())),
+        finished: false,
+    };
+    let mut buffered = vec![];
+    let mut error = None;
+    while buffered.len() <= MAX_BUFFERED_SIZE {
+        match chunks.next() {
+            Some(Ok(chunk)) => buffered.extend_from_slice(&chunk),
+            Some(Err(e)) => {
+                error = Some(e);
+                break;
+            }
+            None => {
+                let length = buffered.len();
+                return (Body::from(buffered), Some(length));
+            }
+        }
+    }
+    let chunks = std::iter::once(Ok(Bytes::from(buffered)))
+        .chain(error.map(Err))
+        .chain(chunks);
+    (Body::wrap_stream(stream::iter(chunks)), None)
+}
+
+/// Iterator over the chunks of encoded and possibly compressed metric families.
+struct EncodedChunks {
+    metric_families: std::vec::IntoIter<MetricFamily>,
+    format: MetricsFormat,
+    gzip: Option<GzEncoder<Vec<u8>>>,
+    finished: bool,
+}
+
+impl EncodedChunks {
+    /// Encode the next metric family or the trailer if there are none left.
+    ///
+    /// Returns `None` once everything has been encoded.
+    fn next_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut chunk = vec![];
+        match self.metric_families.next() {
+            Some(mf) => self
+                .format
+                .encode(std::slice::from_ref(&mf), &mut chunk)
+                .map_err(io::Error::other)?,
+            None if !self.finished => {
+                self.finished = true;
+                chunk.extend_from_slice(self.format.trailer());
+                if let Some(mut encoder) = self.gzip.take() {
+                    encoder.write_all(&chunk)?;
+                    return encoder.finish().map(Some);
+                }
+            }
+            None => return Ok(None),
+        }
+        match &mut self.gzip {
+            Some(encoder) => {
+                encoder.write_all(&chunk)?;
+                // The compressor holds back what it can't compress yet.
+                Ok(Some(std::mem::take(encoder.get_mut())))
+            }
+            None => Ok(Some(chunk)),
+        }
+    }
+}
+
+impl Iterator for EncodedChunks {
+    type Item = io::Result<Bytes>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.next_chunk() {
+                // There's no point in sending empty chunks.
+                Ok(Some(chunk)) if chunk.is_empty() => continue,
+                result => return result.transpose().map(|chunk| chunk.map(Bytes::from)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use pretty_assertions::assert_eq;
+    use prometheus::{Histogram, HistogramOpts, IntCounterVec, Opts, Registry};
+
+    use super::*;
+
+    fn test_metric_families() -> Vec<MetricFamily> {
+        let registry = Registry::new();
+        let requests =
+            IntCounterVec::new(Opts::new("requests_total", "Requests."), &["path"]).unwrap();
+        requests.with_label_values(&["/metrics"]).inc();
+        requests.with_label_values(&["/geolocation"]).inc_by(2);
+        let latency = Histogram::with_opts(HistogramOpts::new("latency", "Latency.")).unwrap();
+        latency.observe(0.3);
+        registry.register(Box::new(requests)).unwrap();
+        registry.register(Box::new(latency)).unwrap();
+        registry.gather()
+    }
+
+    #[tokio::test]
+    /// The streamed body should be exactly what encoding all metrics at once would give us.
+    async fn streamed_equals_buffered() {
+        let metric_families = test_metric_families();
+        let mut text = vec![];
+        TextEncoder::new()
+            .encode(&metric_families, &mut text)
+            .unwrap();
+        let mut openmetrics = vec![];
+        OpenMetricsEncoder::new()
+            .encode(&metric_families, &mut openmetrics)
+            .unwrap();
+        let mut protobuf = vec![];
+        ProtobufEncoder::new()
+            .encode(&metric_families, &mut protobuf)
+            .unwrap();
+        let mut influx = vec![];
+        LineProtocolEncoder::new(42)
+            .encode(&metric_families, &mut influx)
+            .unwrap();
+        for (format, buffered) in [
+            (MetricsFormat::Text, &text),
+            (MetricsFormat::OpenMetrics, &openmetrics),
+            (MetricsFormat::Protobuf, &protobuf),
+            (MetricsFormat::Influx(42), &influx),
+        ] {
+            let format_name = format!("{format:?}");
+            let (streamed, length) = metrics_body(metric_families.clone(), format, false);
+            let streamed = hyper::body::to_bytes(streamed).await.unwrap();
+            assert_eq!(&streamed[..], &buffered[..], "{}", format_name);
+            assert_eq!(length, Some(buffered.len()), "{}", format_name);
+        }
+
+        let (compressed, length) = metrics_body(metric_families, MetricsFormat::Text, true);
+        let compressed = hyper::body::to_bytes(compressed).await.unwrap();
+        assert_eq!(length, Some(compressed.len()));
+        let mut decompressed = vec![];
+        flate2::read::GzDecoder::new(&compressed[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, text);
+    }
+
+    #[tokio::test]
+    /// Metrics too large to buffer should be streamed without a length.
+    async fn large_metrics_are_streamed() {
+        let registry = Registry::new();
+        let requests =
+            IntCounterVec::new(Opts::new("requests_total", "Requests."), &["path"]).unwrap();
+        for i in 0..50_000 {
+            requests.with_label_values(&[&format!("/{i}")]).inc();
+        }
+        registry.register(Box::new(requests)).unwrap();
+        let metric_families = registry.gather();
+        let mut text = vec![];
+        TextEncoder::new()
+            .encode(&metric_families, &mut text)
+            .unwrap();
+        assert!(text.len() > MAX_BUFFERED_SIZE);
+
+        let (streamed, length) = metrics_body(metric_families, MetricsFormat::Text, false);
+        assert_eq!(length, None);
+        let streamed = hyper::body::to_bytes(streamed).await.unwrap();
+        assert_eq!(&streamed[..], &text[..]);
+    }
+}
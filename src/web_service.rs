@@ -1,124 +1,1858 @@
 //! Module containing the web service.
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
+use anyhow::Result;
+use chrono::Utc;
+use clap::crate_version;
+use futures_util::future::join_all;
+use hyper::body::HttpBody;
 use hyper::{header, Body, Method, Request, Response, StatusCode};
 use log::{debug, error, info};
-use prometheus::{Encoder, TextEncoder};
-use tokio::sync::RwLock;
+use prometheus::proto::MetricFamily;
+use thiserror::Error;
+use tokio::sync::{watch, Mutex as AsyncMutex, RwLock, RwLockWriteGuard};
 
-use crate::api_communication::fetch_current_status;
-use crate::metrics::update_metrics_from_current_status;
-use crate::{api_communication::get_access_token, geodata, site24x7_types, CLIENT};
+use crate::api_communication::{
+    fetch_current_status, fetch_current_status_text, get_access_token, RetryPolicy,
+};
+use crate::geodata::SerializedGeoLocationInfo;
+use crate::metrics::{
+    set_access_token_expiry, set_parse_error_path, set_poll_timestamps, set_zoho_api_domain,
+    update_metrics_from_accounts, Metrics, MetricsOptions,
+};
+use crate::site24x7_types;
+use crate::streaming::{metrics_body, MetricsFormat};
 
-pub async fn hyper_service(
-    req: Request<Body>,
-    site24x7_client_info: &site24x7_types::Site24x7ClientInfo,
-    refresh_token: &str,
-    access_token: Arc<RwLock<String>>,
-    metrics_path: &str,
-    geolocation_path: &str,
-) -> Result<Response<Body>, hyper::Error> {
-    // Serve geolocation data.
-    if req.method() == Method::GET && req.uri().path() == geolocation_path {
-        info!("Serving geolocation info");
-        return Ok(Response::builder()
-            .header("Content-Type", "application/json")
-            .header("Access-Control-Allow-Origin", "*")
-            .body(Body::from(
-                serde_json::to_string_pretty(&geodata::get_geolocation_info()).unwrap(),
-            ))
-            .unwrap());
+/// A Site24x7 account to fetch data from along with everything needed to do so.
+pub struct Account {
+    /// Used as the `account` label if metrics have one.
+    pub name: String,
+    pub site24x7_client_info: site24x7_types::Site24x7ClientInfo,
+    /// `None` if we were given an access token directly which we then can't renew.
+    pub refresh_token: Option<String>,
+    /// An access token is only valid for a limited time so we'll have to replace it from time to
+    /// time.
+    pub access_token: RwLock<String>,
+    /// When the access token expires if we know it.
+    pub access_token_expires_at: Mutex<Option<Instant>>,
+    /// Until when we must not ask Site24x7 for data as we've been rate limited.
+    pub rate_limited_until: Mutex<Option<Instant>>,
+}
+
+/// State shared between all requests handled by the web service.
+pub struct AppState {
+    pub client: reqwest::Client,
+    /// The accounts to fetch data from, usually just one.
+    pub accounts: Vec<Account>,
+    pub metrics_path: String,
+    /// Where to serve geolocation info unless it's disabled.
+    pub geolocation_path: Option<String>,
+    /// Whether to answer requests to `/` with 404 instead of a landing page.
+    pub disable_landing_page: bool,
+    /// Where to serve the raw API response, if at all, as it might be sensitive.
+    pub debug_path: Option<String>,
+    /// Where to serve metrics in the InfluxDB line protocol, if at all.
+    pub influx_path: Option<String>,
+    /// Sent as `Access-Control-Allow-Origin` with the geolocation info unless empty.
+    pub cors_allow_origin: String,
+    /// Requests with a larger body than this many bytes are rejected without reading it.
+    pub max_request_body_size: u64,
+    pub metrics: Metrics,
+    pub metrics_options: MetricsOptions,
+    pub retry_policy: RetryPolicy,
+    /// Whether we have managed to fetch data from Site24x7 at least once.
+    pub ready: AtomicBool,
+    pub geolocation_info: SerializedGeoLocationInfo,
+    /// How long fetched data may be reused for instead of fetching it again.
+    pub cache_ttl: Duration,
+    /// The last successfully fetched data of every account in order and when it was fetched.
+    pub cached_current_status: RwLock<Option<(Instant, Vec<site24x7_types::CurrentStatusData>)>>,
+    /// The last successfully fetched data of every account in order, if any, so that an account
+    /// failing to be fetched doesn't drop out of the totals.
+    pub last_current_status: Mutex<Vec<Option<site24x7_types::CurrentStatusData>>>,
+    /// Held while refreshing metrics so that concurrent scrapes don't fetch the same data twice.
+    pub refresh_lock: AsyncMutex<()>,
+    /// Number of completed metric refreshes.
+    pub refreshes: AtomicU64,
+    /// When the exporter was started.
+    pub started_at: Instant,
+    /// Shut down after answering this many scrapes successfully if set.
+    pub exit_after_requests: Option<u64>,
+    /// Number of scrapes answered successfully.
+    pub scrapes_served: AtomicU64,
+    /// Turns `true` once the servers should shut down gracefully.
+    pub shutdown: watch::Sender<bool>,
+}
+
+/// How long clients may cache geolocation info as it only changes with new releases.
+const GEOLOCATION_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Path which reports whether the process is alive, following the Prometheus convention.
+pub const HEALTHY_PATH: &str = "/-/healthy";
+
+/// Path which reports whether we're ready to serve metrics, following the Prometheus convention.
+pub const READY_PATH: &str = "/-/ready";
+
+/// Path of the landing page pointing to everything else we serve.
+pub const ROOT_PATH: &str = "/";
+
+/// Reasons for not being able to answer a request as asked.
+#[derive(Error, Debug)]
+pub enum WebError {
+    /// Something went wrong on our side, e.g. a response couldn't be built.
+    #[error("Internal server error")]
+    Internal(#[from] anyhow::Error),
+
+    /// Site24x7 didn't give us what we needed.
+    #[error("Couldn't fetch current status: {0}")]
+    Upstream(#[from] site24x7_types::CurrentStatusError),
+
+    /// We haven't managed to fetch data from Site24x7 yet.
+    #[error("Not ready")]
+    NotReady,
+
+    /// The request's body exceeds the given limit in bytes.
+    #[error("Request body is larger than {0} bytes")]
+    PayloadTooLarge(u64),
+}
+
+impl From<hyper::http::Error> for WebError {
+    fn from(e: hyper::http::Error) -> Self {
+        WebError::Internal(e.into())
     }
+}
 
-    // Serve default path.
-    if req.method() != Method::GET || req.uri().path() != metrics_path {
-        info!("Serving default path");
-        return Ok(Response::new(
-            format!("site24x7_exporter\n\nTry {metrics_path}").into(),
-        ));
+impl From<prometheus::Error> for WebError {
+    fn from(e: prometheus::Error) -> Self {
+        WebError::Internal(e.into())
     }
+}
 
-    info!("Serving metrics");
+impl WebError {
+    /// The status code to answer with.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            WebError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            WebError::Upstream(_) => StatusCode::BAD_GATEWAY,
+            WebError::NotReady => StatusCode::SERVICE_UNAVAILABLE,
+            WebError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+        }
+    }
+}
+
+impl From<WebError> for Response<Body> {
+    fn from(e: WebError) -> Self {
+        let mut resp = Response::new(Body::from(format!("{e}\n")));
+        *resp.status_mut() = e.status();
+        resp
+    }
+}
+
+/// Escape `s` for use in HTML text and attribute values.
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Render a minimal HTML page linking to the paths we serve.
+fn landing_page(state: &AppState) -> String {
+    let link =
+        |path: &str, text: &str| format!("<li><a href=\"{}\">{text}</a></li>\n", escape_html(path));
+    let mut links = link(&state.metrics_path, "Metrics");
+    if let Some(influx_path) = &state.influx_path {
+        links.push_str(&link(influx_path, "Metrics (InfluxDB line protocol)"));
+    }
+    if let Some(geolocation_path) = &state.geolocation_path {
+        links.push_str(&link(geolocation_path, "Geolocation info"));
+    }
+    format!(
+        "<!DOCTYPE html>
+<html>
+<head><title>Site24x7 Exporter</title></head>
+<body>
+<h1>Site24x7 Exporter</h1>
+<p>Version {}</p>
+<ul>
+{links}</ul>
+</body>
+</html>
+",
+        crate_version!()
+    )
+}
+
+/// Return the values listed in the `Accept`-like header `header_name` of `req` without their
+/// parameters.
+///
+/// Values the client explicitly refused by giving them a quality of 0 are left out.
+fn accepted_values(req: &Request<Body>, header_name: header::HeaderName) -> Vec<&str> {
+    req.headers()
+        .get_all(header_name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|value| {
+            let mut params = value.split(';').map(str::trim);
+            let name = params.next().unwrap_or_default();
+            let refused = params.any(|p| {
+                p.strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q == 0.0)
+            });
+            (!refused).then_some(name)
+        })
+        .collect()
+}
+
+/// Return whether the client indicated that it accepts gzip-compressed responses.
+fn accepts_gzip(req: &Request<Body>) -> bool {
+    accepted_values(req, header::ACCEPT_ENCODING)
+        .iter()
+        .any(|encoding| encoding.eq_ignore_ascii_case("gzip") || *encoding == "*")
+}
+
+/// Return whether the client already has the representation identified by `etag` according to
+/// its `If-None-Match` header.
+fn etag_matches(req: &Request<Body>, etag: &str) -> bool {
+    req.headers()
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// Return whether the client asked for metrics in the OpenMetrics format.
+fn accepts_openmetrics(req: &Request<Body>) -> bool {
+    accepted_values(req, header::ACCEPT)
+        .iter()
+        .any(|media_type| media_type.eq_ignore_ascii_case("application/openmetrics-text"))
+}
+
+/// Return whether the client asked for metrics in the delimited protobuf format.
+fn accepts_protobuf(req: &Request<Body>) -> bool {
+    accepted_values(req, header::ACCEPT)
+        .iter()
+        .any(|media_type| media_type.eq_ignore_ascii_case("application/vnd.google.protobuf"))
+}
+
+/// Return whether the client asked for geolocation info as GeoJSON.
+fn accepts_geojson(req: &Request<Body>) -> bool {
+    query_values(req, "format")
+        .iter()
+        .any(|format| format.eq_ignore_ascii_case("geojson"))
+        || accepted_values(req, header::ACCEPT)
+            .iter()
+            .any(|media_type| media_type.eq_ignore_ascii_case("application/geo+json"))
+}
+
+/// Return the values of the query parameter `name` of `req`.
+fn query_values(req: &Request<Body>, name: &str) -> Vec<String> {
+    let Some(query) = req.uri().query() else {
+        return vec![];
+    };
+    // hyper doesn't decode queries so we'll borrow that from `Url`.
+    let Ok(url) = reqwest::Url::parse(&format!("http://localhost/?{query}")) else {
+        return vec![];
+    };
+    url.query_pairs()
+        .filter(|(key, _)| key == name)
+        .map(|(_, value)| value.into_owned())
+        .collect()
+}
+
+/// Only keep the series of `metric_families` that have a `monitor_group` label with one of
+/// `monitor_groups` as its value.
+///
+/// Metric families that end up without any series are dropped entirely.
+fn filter_monitor_groups(
+    metric_families: Vec<MetricFamily>,
+    monitor_groups: &[String],
+) -> Vec<MetricFamily> {
+    metric_families
+        .into_iter()
+        .filter_map(|mut metric_family| {
+            let metrics: Vec<_> = metric_family
+                .take_metric()
+                .into_iter()
+                .filter(|metric| {
+                    metric.get_label().iter().any(|l| {
+                        l.get_name() == "monitor_group"
+                            && monitor_groups.iter().any(|g| g == l.get_value())
+                    })
+                })
+                .collect();
+            if metrics.is_empty() {
+                return None;
+            }
+            metric_family.set_metric(metrics.into());
+            Some(metric_family)
+        })
+        .collect()
+}
+
+/// Fetch the current status while keeping track of how long it took and whether it failed.
+async fn timed_fetch_current_status(
+    state: &AppState,
+    account: &Account,
+    access_token: &str,
+) -> Result<site24x7_types::CurrentStatusData, site24x7_types::CurrentStatusError> {
+    // Asking again while we're rate limited would only prolong our penalty.
+    let rate_limited_until = *account.rate_limited_until.lock().unwrap();
+    if let Some(remaining) =
+        rate_limited_until.and_then(|until| until.checked_duration_since(Instant::now()))
+    {
+        info!("Not fetching current status as we're rate limited for another {remaining:?}");
+        let e = site24x7_types::CurrentStatusError::RateLimited(Some(remaining));
+        state
+            .metrics
+            .scrape_errors
+            .with_label_values(&[e.kind()])
+            .inc();
+        return Err(e);
+    }
+
+    let timer = state.metrics.scrape_duration_seconds.start_timer();
+    let current_status = fetch_current_status(
+        &state.client,
+        &account.site24x7_client_info.site24x7_endpoint,
+        &account.site24x7_client_info.api_version,
+        access_token,
+        &state.retry_policy,
+    )
+    .await;
+    timer.observe_duration();
+
+    match &current_status {
+        Ok(_) => state.ready.store(true, Ordering::Relaxed),
+        Err(e) => {
+            state
+                .metrics
+                .scrape_errors
+                .with_label_values(&[e.kind()])
+                .inc();
+            if let Some(path) = e.json_path() {
+                set_parse_error_path(&state.metrics, path);
+            }
+            if let site24x7_types::CurrentStatusError::RateLimited(Some(retry_after)) = e {
+                *account.rate_limited_until.lock().unwrap() = Some(Instant::now() + *retry_after);
+            }
+        }
+    }
+    current_status
+}
+
+/// Fetch fresh data of `account` from Site24x7, renewing its access token if need be.
+///
+/// Returns `None` if that didn't work out which has been logged already.
+async fn fetch_account(
+    state: &AppState,
+    account: &Account,
+) -> Option<site24x7_types::CurrentStatusData> {
     let current_status;
     {
-        let access_token_read = access_token.read().await;
+        let access_token_read = account.access_token.read().await;
 
-        current_status = fetch_current_status(
-            &CLIENT,
-            &site24x7_client_info.site24x7_endpoint,
-            &access_token_read,
-        )
-        .await;
+        current_status = timed_fetch_current_status(state, account, &access_token_read).await;
     }
 
-    let current_status_data = match current_status {
-        Ok(ref current_status_data) => {
+    match current_status {
+        Ok(current_status_data) => {
             debug!(
                 "Successfully deserialized into this data structure: \n{:#?}",
-                &current_status
+                &current_status_data
             );
-            current_status_data.clone()
+            Some(current_status_data)
         }
         // If there was an auth error, maybe the token was old. We'll try to get a new token.
         // If we also get an auth error the second time, probably something is wrong with the
         // refresh token and we'll just give up.
+        Err(site24x7_types::CurrentStatusError::ApiAuthError(e))
+            if account.refresh_token.is_none() =>
+        {
+            error!(
+                "Couldn't get status update due to an authentication error ({e}). \
+                The access token was provided directly and can't be renewed. \
+                Provide a refresh token instead so that it can be renewed automatically."
+            );
+            None
+        }
         Err(site24x7_types::CurrentStatusError::ApiAuthError(_)) => {
             info!(
                 "Couldn't get status update due to an authentication error. \
                 Probably the access token has timed out. Trying to get a new one."
             );
-            let mut access_token_write = access_token.write().await;
-            let access_token_res =
-                get_access_token(&CLIENT, site24x7_client_info, refresh_token).await;
-            *access_token_write = match access_token_res {
-                Ok(access_token) => access_token,
-                Err(e) => {
-                    error!("Failed to renew access token");
-                    error!("{:?}", e);
-                    return Ok(Response::builder()
-                        .status(StatusCode::INTERNAL_SERVER_ERROR)
-                        .body(Body::from(e.to_string()))
-                        .unwrap());
-                }
-            };
-
-            match fetch_current_status(
-                &CLIENT,
-                &site24x7_client_info.site24x7_endpoint,
-                &access_token_write,
-            )
-            .await
-            {
-                Ok(current_status_data) => current_status_data,
+            let access_token_write = renew_access_token(state, account).await?;
+            match timed_fetch_current_status(state, account, &access_token_write).await {
+                Ok(current_status_data) => Some(current_status_data),
                 Err(e) => {
                     error!("An unexpected error occurred after renewing access token.");
                     error!("{:?}", e);
-                    return Ok(Response::builder()
-                        .status(StatusCode::INTERNAL_SERVER_ERROR)
-                        .body(Body::from(e.to_string()))
-                        .unwrap());
+                    None
                 }
             }
         }
         Err(e) => {
             error!("An unexpected error occurred.");
             error!("{:?}", e);
-            return Ok(Response::builder()
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .body(Body::from(e.to_string()))
-                .unwrap());
+            None
+        }
+    }
+}
+
+/// Get a new access token for `account` using its refresh token and keep it for later requests.
+///
+/// Returns the new token, still locked so that it can be used right away, or `None` if it couldn't
+/// be renewed.
+async fn renew_access_token<'a>(
+    state: &AppState,
+    account: &'a Account,
+) -> Option<RwLockWriteGuard<'a, String>> {
+    let mut access_token_write = account.access_token.write().await;
+    state.metrics.token_refresh.inc();
+    let access_token_res = get_access_token(
+        &state.client,
+        &account.site24x7_client_info,
+        account.refresh_token.as_deref().unwrap_or_default(),
+        &state.retry_policy,
+    )
+    .await;
+    match access_token_res {
+        Ok(access_token) => {
+            *access_token_write = access_token.access_token;
+            *account.access_token_expires_at.lock().unwrap() =
+                Instant::now().checked_add(access_token.expires_in);
+            set_zoho_api_domain(&state.metrics, &access_token.api_domain);
+            Some(access_token_write)
+        }
+        Err(e) => {
+            state.metrics.token_refresh_failures.inc();
+            error!("Failed to renew access token");
+            error!("{:?}", e);
+            None
+        }
+    }
+}
+
+/// Fetch the raw current status of `account`, renewing its access token like [`fetch_account`]
+/// does if it was rejected.
+async fn fetch_raw_current_status(
+    state: &AppState,
+    account: &Account,
+) -> Result<String, site24x7_types::CurrentStatusError> {
+    let site24x7_client_info = &account.site24x7_client_info;
+    let current_status_text = {
+        let access_token_read = account.access_token.read().await;
+        fetch_current_status_text(
+            &state.client,
+            &site24x7_client_info.site24x7_endpoint,
+            &site24x7_client_info.api_version,
+            &access_token_read,
+            &state.retry_policy,
+        )
+        .await
+    };
+
+    // The raw response isn't parsed so a rejected token might still come as a successful one.
+    let rejected = match &current_status_text {
+        Err(site24x7_types::CurrentStatusError::ApiAuthError(_)) => true,
+        Ok(text) => {
+            serde_json::from_str::<site24x7_types::ApiError>(text).is_ok_and(|e| e.is_auth_error())
+        }
+        Err(_) => false,
+    };
+    if !rejected || account.refresh_token.is_none() {
+        return current_status_text;
+    }
+    info!("The access token was rejected. Trying to get a new one.");
+    let Some(access_token_write) = renew_access_token(state, account).await else {
+        return current_status_text;
+    };
+    fetch_current_status_text(
+        &state.client,
+        &site24x7_client_info.site24x7_endpoint,
+        &site24x7_client_info.api_version,
+        &access_token_write,
+        &state.retry_policy,
+    )
+    .await
+}
+
+/// Fetch fresh data of all accounts from Site24x7 and update the metrics with it.
+///
+/// Concurrent calls are coalesced: a call arriving while another one is fetching waits for it and
+/// then reuses its result rather than fetching the same data again.
+async fn refresh_metrics(state: &AppState) {
+    let refreshes_seen = state.refreshes.load(Ordering::SeqCst);
+    let _refresh_guard = state.refresh_lock.lock().await;
+    if state.refreshes.load(Ordering::SeqCst) != refreshes_seen {
+        debug!("Reusing metrics of a concurrent refresh");
+        return;
+    }
+
+    // Data that is recent enough is applied again rather than fetched again. We don't just skip
+    // the update as some metrics depend on the current time. Latencies aren't observed again as
+    // their poll times haven't changed.
+    if let Some((fetched_at, current_status_data)) = &*state.cached_current_status.read().await {
+        if fetched_at.elapsed() < state.cache_ttl {
+            debug!(
+                "Reusing current status fetched {:?} ago",
+                fetched_at.elapsed()
+            );
+            let accounts: Vec<_> = state
+                .accounts
+                .iter()
+                .map(|account| account.name.as_str())
+                .zip(current_status_data)
+                .collect();
+            update_metrics_from_accounts(&state.metrics, &accounts, &state.metrics_options);
+            return;
+        }
+    }
+
+    let current_status_data = join_all(
+        state
+            .accounts
+            .iter()
+            .map(|account| fetch_account(state, account)),
+    )
+    .await;
+
+    // If we couldn't get fresh data, we'll keep serving the metrics from the last successful
+    // scrape so that dashboards stay usable during upstream hiccups. Whether the data is stale can
+    // be told from `site24x7_last_scrape_success`. Accounts that did work out are updated anyway
+    // while the others are applied again with their last good data so that the totals still cover
+    // all of them.
+    {
+        let mut last_current_status = state.last_current_status.lock().unwrap();
+        last_current_status.resize(state.accounts.len(), None);
+        for (last, fresh) in last_current_status.iter_mut().zip(&current_status_data) {
+            if let Some(fresh) = fresh {
+                *last = Some(fresh.clone());
+            }
+        }
+        let accounts: Vec<_> = state
+            .accounts
+            .iter()
+            .zip(last_current_status.iter())
+            .filter_map(|(account, data)| Some((account.name.as_str(), data.as_ref()?)))
+            .collect();
+        if !accounts.is_empty() {
+            update_metrics_from_accounts(&state.metrics, &accounts, &state.metrics_options);
+        }
+    }
+    if let Some(current_status_data) = current_status_data.into_iter().collect::<Option<Vec<_>>>() {
+        state.metrics.last_scrape_success.set(1);
+        state.metrics.parse_error.reset();
+        state
+            .metrics
+            .last_scrape_timestamp_seconds
+            .set(Utc::now().timestamp_millis() as f64 / 1000.0);
+        if !state.cache_ttl.is_zero() {
+            *state.cached_current_status.write().await =
+                Some((Instant::now(), current_status_data));
+        }
+    } else {
+        state.metrics.last_scrape_success.set(0);
+    }
+
+    state.refreshes.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Handle a request and log it afterwards.
+pub async fn hyper_service(
+    req: Request<Body>,
+    state: &AppState,
+) -> Result<Response<Body>, hyper::Error> {
+    let start = Instant::now();
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let is_scrape =
+        path == state.metrics_path || state.influx_path.as_deref() == Some(path.as_str());
+
+    let resp = match handle_request(req, state).await {
+        Ok(resp) => resp,
+        Err(e) => {
+            // Only our own failures are worth an error, the others are logged where they occur.
+            if let WebError::Internal(_) = e {
+                error!("Couldn't handle request: {:?}", e);
+            }
+            e.into()
         }
     };
 
-    update_metrics_from_current_status(&current_status_data);
+    info!(
+        "{}",
+        access_log_line(&method, &path, resp.status(), start.elapsed())
+    );
+
+    if is_scrape && resp.status() == StatusCode::OK {
+        let scrapes_served = state.scrapes_served.fetch_add(1, Ordering::SeqCst) + 1;
+        if state.exit_after_requests == Some(scrapes_served) {
+            info!("Shutting down after {scrapes_served} scrapes");
+            state.shutdown.send_replace(true);
+        }
+    }
+    Ok(resp)
+}
+
+/// Describe a request that was answered with `status` after `duration` for the access log.
+fn access_log_line(method: &Method, path: &str, status: StatusCode, duration: Duration) -> String {
+    format!(
+        "method={} path={} status={} duration_seconds={:.3}",
+        method,
+        path,
+        status.as_u16(),
+        duration.as_secs_f64()
+    )
+}
+
+async fn handle_request(req: Request<Body>, state: &AppState) -> Result<Response<Body>, WebError> {
+    // We don't read any bodies so far but we won't even start to if they're known to be too large.
+    if req.body().size_hint().lower() > state.max_request_body_size {
+        info!("Rejecting request with a body that's too large");
+        return Err(WebError::PayloadTooLarge(state.max_request_body_size));
+    }
+    // Bodies without a declared length, e.g. chunked ones, can only be told to be too large while
+    // they're read. We read them for that alone.
+    let (parts, mut body) = req.into_parts();
+    let mut body_size = 0;
+    while let Some(chunk) = body.data().await {
+        match chunk {
+            Ok(chunk) => body_size += chunk.len() as u64,
+            Err(e) => {
+                debug!("Couldn't read request body: {e}");
+                break;
+            }
+        }
+        if body_size > state.max_request_body_size {
+            info!("Rejecting request with a body that's too large");
+            return Err(WebError::PayloadTooLarge(state.max_request_body_size));
+        }
+    }
+    let req = Request::from_parts(parts, Body::empty());
+
+    let metrics_path = state.metrics_path.as_str();
+    let geolocation_path = state.geolocation_path.as_deref();
+
+    // Only GET is supported on the known paths.
+    let path = req.uri().path();
+    let debug_path = state.debug_path.as_deref();
+    let influx_path = state.influx_path.as_deref();
+    let is_known_path = [metrics_path, HEALTHY_PATH, READY_PATH].contains(&path)
+        || (path == ROOT_PATH && !state.disable_landing_page)
+        || geolocation_path == Some(path)
+        || debug_path == Some(path)
+        || influx_path == Some(path);
+    if is_known_path && req.method() != Method::GET {
+        info!("Rejecting {} request to {}", req.method(), path);
+        return Ok(Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .header(header::ALLOW, "GET")
+            .body(Body::empty())?);
+    }
+
+    // Health checks are cheap and never talk to the API.
+    if path == HEALTHY_PATH {
+        return Ok(Response::new("Healthy\n".into()));
+    }
+    if path == READY_PATH {
+        if !state.ready.load(Ordering::Relaxed) {
+            return Err(WebError::NotReady);
+        }
+        return Ok(Response::new("Ready\n".into()));
+    }
+
+    // Serve geolocation data, as GeoJSON if asked for with `?format=geojson` or `Accept`.
+    if geolocation_path == Some(path) {
+        info!("Serving geolocation info");
+        let (document, content_type) = if accepts_geojson(&req) {
+            (&state.geolocation_info.geojson, "application/geo+json")
+        } else {
+            (&state.geolocation_info.json, "application/json")
+        };
+        let mut response = Response::builder()
+            .header("Content-Type", content_type)
+            .header(header::VARY, "Accept, Accept-Encoding")
+            .header(
+                header::CACHE_CONTROL,
+                format!("public, max-age={}", GEOLOCATION_MAX_AGE.as_secs()),
+            )
+            .header(header::ETAG, document.etag.as_str());
+        if !state.cors_allow_origin.is_empty() {
+            response = response.header(
+                header::ACCESS_CONTROL_ALLOW_ORIGIN,
+                state.cors_allow_origin.as_str(),
+            );
+        }
+        return Ok(if etag_matches(&req, &document.etag) {
+            response
+                .status(StatusCode::NOT_MODIFIED)
+                .body(Body::empty())?
+        } else if accepts_gzip(&req) {
+            response
+                .header(header::CONTENT_ENCODING, "gzip")
+                .body(Body::from(document.gzip.clone()))?
+        } else {
+            response.body(Body::from(document.plain.clone()))?
+        });
+    }
 
-    let metric_families = prometheus::gather();
-    let mut buffer = vec![];
-    let encoder = TextEncoder::new();
-    encoder.encode(&metric_families, &mut buffer).unwrap();
-    Ok(Response::builder()
+    // Serve the raw API response to help with figuring out why something isn't parsed properly.
+    // It's the first account's unless another one is asked for with `?account=customer`.
+    if debug_path == Some(path) {
+        let account = match query_values(&req, "account").first() {
+            Some(name) => state.accounts.iter().find(|account| &account.name == name),
+            None => state.accounts.first(),
+        };
+        let Some(account) = account else {
+            return Ok(Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body("Unknown account\n".into())?);
+        };
+        info!("Serving raw current status");
+        let current_status_text = fetch_raw_current_status(state, account).await?;
+        // Pretty-print if possible but broken JSON is exactly what we might be after.
+        let body = serde_json::from_str::<serde_json::Value>(&current_status_text)
+            .and_then(|v| serde_json::to_string_pretty(&v))
+            .unwrap_or(current_status_text);
+        return Ok(Response::builder()
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(body.into())?);
+    }
+
+    if path == ROOT_PATH && path != metrics_path {
+        // Some don't want to give away anything that isn't needed, not even a hint.
+        if state.disable_landing_page {
+            info!("Not serving disabled landing page");
+            return Ok(Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::empty())?);
+        }
+        info!("Serving landing page");
+        return Ok(Response::builder()
+            .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+            .body(landing_page(state).into())?);
+    }
+
+    // Anything else is not found but we'll still point the user in the right direction.
+    if path != metrics_path && influx_path != Some(path) {
+        info!("Serving default path");
+        return Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(format!("site24x7_exporter\n\nTry {metrics_path}").into())?);
+    }
+
+    info!("Serving metrics");
+    refresh_metrics(state).await;
+    state
+        .metrics
+        .uptime_seconds
+        .set(state.started_at.elapsed().as_secs_f64());
+    // The access token that expires first is the one to worry about.
+    let access_token_expires_at = state
+        .accounts
+        .iter()
+        .filter_map(|account| *account.access_token_expires_at.lock().unwrap())
+        .min();
+    if let Some(expires_at) = access_token_expires_at {
+        set_access_token_expiry(&state.metrics, expires_at);
+    }
+
+    // Scrapes can be narrowed down to some monitor groups with `?group=production`.
+    let mut metric_families = state.metrics.registry.gather();
+    let monitor_groups = query_values(&req, "group");
+    if !monitor_groups.is_empty() {
+        metric_families = filter_monitor_groups(metric_families, &monitor_groups);
+    }
+    if state.metrics_options.use_poll_timestamp {
+        set_poll_timestamps(&state.metrics, &mut metric_families);
+    }
+    let format = if influx_path == Some(path) {
+        MetricsFormat::Influx(Utc::now().timestamp_nanos_opt().unwrap_or_default())
+    } else if accepts_protobuf(&req) {
+        MetricsFormat::Protobuf
+    } else if accepts_openmetrics(&req) {
+        MetricsFormat::OpenMetrics
+    } else {
+        MetricsFormat::Text
+    };
+    let mut response = Response::builder()
         .status(StatusCode::OK)
-        .header(header::CONTENT_ENCODING, encoder.format_type())
-        .body(Body::from(buffer))
-        .unwrap())
+        .header(header::CONTENT_TYPE, format.content_type())
+        .header(header::VARY, "Accept, Accept-Encoding");
+    let gzip = accepts_gzip(&req);
+    if gzip {
+        response = response.header(header::CONTENT_ENCODING, "gzip");
+    }
+    // Large metrics are encoded while they're sent so we can only tell the length of small ones.
+    let (body, length) = metrics_body(metric_families, format, gzip);
+    if let Some(length) = length {
+        response = response.header(header::CONTENT_LENGTH, length);
+    }
+    Ok(response.body(body)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::Server;
+    use pretty_assertions::assert_eq;
+
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::api_communication::DEFAULT_API_VERSION;
+    use crate::openmetrics::OPENMETRICS_FORMAT;
+
+    /// Spawn a server pretending to be the Site24x7 API which replies to everything with `body`.
+    ///
+    /// Returns the API endpoint to use.
+    async fn spawn_mock_api(body: &'static str) -> String {
+        spawn_mock_api_with_status(StatusCode::OK, &[], body)
+            .await
+            .0
+    }
+
+    /// Like `spawn_mock_api` but replies with the given `status` and `headers`.
+    ///
+    /// Also returns the number of requests the server has received so far.
+    async fn spawn_mock_api_with_status(
+        status: StatusCode,
+        headers: &'static [(&'static str, &'static str)],
+        body: &'static str,
+    ) -> (String, Arc<AtomicUsize>) {
+        spawn_mock_api_with(status, headers, body, Duration::ZERO).await
+    }
+
+    /// Like `spawn_mock_api_with_status` but waits for `delay` before every reply.
+    async fn spawn_mock_api_with(
+        status: StatusCode,
+        headers: &'static [(&'static str, &'static str)],
+        body: &'static str,
+        delay: Duration,
+    ) -> (String, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let requests_seen = requests.clone();
+        let make_service = make_service_fn(move |_conn| {
+            let requests = requests.clone();
+            async move {
+                Ok::<_, hyper::Error>(service_fn(move |_req| {
+                    requests.fetch_add(1, Ordering::SeqCst);
+                    let mut resp = Response::builder().status(status);
+                    for (name, value) in headers {
+                        resp = resp.header(*name, *value);
+                    }
+                    async move {
+                        tokio::time::sleep(delay).await;
+                        resp.body(Body::from(body))
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let endpoint = format!("http://{}/api", server.local_addr());
+        tokio::spawn(server);
+        (endpoint, requests_seen)
+    }
+
+    fn test_state() -> AppState {
+        test_state_with_endpoint("http://127.0.0.1:1/api")
+    }
+
+    fn test_account(name: &str, site24x7_endpoint: &str) -> Account {
+        Account {
+            name: name.to_string(),
+            site24x7_client_info: site24x7_types::Site24x7ClientInfo {
+                site24x7_endpoint: site24x7_endpoint.to_string(),
+                api_version: DEFAULT_API_VERSION.to_string(),
+                zoho_endpoint: "http://127.0.0.1:1".to_string(),
+                client_id: "id".to_string(),
+                client_secret: "secret".to_string(),
+            },
+            refresh_token: Some("refresh".to_string()),
+            access_token: RwLock::new("access".to_string()),
+            access_token_expires_at: Mutex::new(None),
+            rate_limited_until: Mutex::new(None),
+        }
+    }
+
+    fn test_state_with_endpoint(site24x7_endpoint: &str) -> AppState {
+        AppState {
+            client: reqwest::Client::new(),
+            accounts: vec![test_account("", site24x7_endpoint)],
+            metrics_path: "/metrics".to_string(),
+            geolocation_path: Some("/geolocation".to_string()),
+            disable_landing_page: false,
+            debug_path: None,
+            influx_path: None,
+            cors_allow_origin: "*".to_string(),
+            max_request_body_size: 1024,
+            metrics: Metrics::new(&Default::default()).unwrap(),
+            metrics_options: MetricsOptions::default(),
+            retry_policy: RetryPolicy::default(),
+            ready: AtomicBool::new(false),
+            geolocation_info: SerializedGeoLocationInfo::new(
+                &crate::geodata::get_geolocation_info(),
+            )
+            .unwrap(),
+            cache_ttl: Duration::ZERO,
+            cached_current_status: RwLock::new(None),
+            last_current_status: Mutex::new(vec![]),
+            refresh_lock: AsyncMutex::new(()),
+            refreshes: AtomicU64::new(0),
+            started_at: Instant::now(),
+            exit_after_requests: None,
+            scrapes_served: AtomicU64::new(0),
+            shutdown: watch::Sender::new(false),
+        }
+    }
+
+    async fn request(method: Method, path: &str) -> Response<Body> {
+        let req = Request::builder()
+            .method(method)
+            .uri(path)
+            .body(Body::empty())
+            .unwrap();
+        hyper_service(req, &test_state()).await.unwrap()
+    }
+
+    #[tokio::test]
+    /// The root should be an HTML page linking to the metrics.
+    async fn root_is_landing_page() {
+        let resp = request(Method::GET, "/").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers()[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("text/html"));
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.contains(r#"<a href="/metrics">"#));
+        assert!(body.contains(r#"<a href="/geolocation">"#));
+        assert!(body.contains(crate_version!()));
+    }
+
+    #[tokio::test]
+    /// The root should be not found without any hint if the landing page is disabled.
+    async fn root_without_landing_page() {
+        let state = AppState {
+            disable_landing_page: true,
+            ..test_state()
+        };
+        let req = Request::get("/").body(Body::empty()).unwrap();
+        let resp = hyper_service(req, &state).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(body.is_empty());
+
+        let req = Request::get("/metrics").body(Body::empty()).unwrap();
+        let resp = hyper_service(req, &state).await.unwrap();
+        assert_ne!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    /// Unknown paths should be reported as such while still giving a helpful hint.
+    async fn unknown_path_is_not_found() {
+        let resp = request(Method::GET, "/bogus").await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("Try /metrics"));
+    }
+
+    #[tokio::test]
+    /// Known paths only support GET.
+    async fn post_to_metrics_is_not_allowed() {
+        let resp = request(Method::POST, "/metrics").await;
+        assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(resp.headers()[header::ALLOW], "GET");
+    }
+
+    #[tokio::test]
+    /// Fetching from upstream should be timed.
+    async fn scrape_duration_is_observed() {
+        let endpoint =
+            spawn_mock_api(include_str!("../tests/data/simple_two_locations.json")).await;
+        let state = test_state_with_endpoint(&endpoint);
+        let req = Request::get("/metrics").body(Body::empty()).unwrap();
+        let resp = hyper_service(req, &state).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(state
+            .metrics
+            .registry
+            .gather()
+            .iter()
+            .any(|mf| mf.get_name() == "site24x7_scrape_duration_seconds"));
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("site24x7_scrape_duration_seconds_count"));
+    }
+
+    #[tokio::test]
+    /// Being rate limited should be reported as such and we should then wait as long as we're
+    /// told to before asking again.
+    async fn rate_limited_scrape_respects_retry_after() {
+        let (endpoint, requests) = spawn_mock_api_with_status(
+            StatusCode::TOO_MANY_REQUESTS,
+            &[("Retry-After", "60")],
+            "Too many requests",
+        )
+        .await;
+        let state = test_state_with_endpoint(&endpoint);
+        let before = state
+            .metrics
+            .scrape_errors
+            .with_label_values(&["rate_limited"])
+            .get();
+        for _ in 0..2 {
+            let req = Request::get("/metrics").body(Body::empty()).unwrap();
+            let resp = hyper_service(req, &state).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            state
+                .metrics
+                .scrape_errors
+                .with_label_values(&["rate_limited"])
+                .get(),
+            before + 2
+        );
+        assert!(state.accounts[0]
+            .rate_limited_until
+            .lock()
+            .unwrap()
+            .is_some());
+    }
+
+    #[tokio::test]
+    /// An unsuccessful status should be reported with the status rather than as a parse error.
+    async fn server_error_is_not_a_parse_error() {
+        let (endpoint, _) = spawn_mock_api_with_status(
+            StatusCode::SERVICE_UNAVAILABLE,
+            &[],
+            "<html>Service Unavailable</html>",
+        )
+        .await;
+        let err = fetch_current_status(
+            &reqwest::Client::new(),
+            &endpoint,
+            DEFAULT_API_VERSION,
+            "access",
+            &RetryPolicy::default(),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.kind(), "unknown");
+        assert!(err.to_string().contains("503 Service Unavailable"));
+    }
+
+    #[tokio::test]
+    /// Scrapes can be limited to a monitor group.
+    async fn metrics_can_be_filtered_by_group() {
+        let endpoint = spawn_mock_api(include_str!("../tests/data/full.json")).await;
+        let state = test_state_with_endpoint(&endpoint);
+        let req = Request::get("/metrics?group=production")
+            .body(Body::empty())
+            .unwrap();
+        let resp = hyper_service(req, &state).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.contains(r#"monitor_group="production""#));
+        assert!(!body.contains(r#"monitor_group="integration""#));
+        assert!(!body.contains(r#"monitor_group="""#));
+        assert!(!body.contains("site24x7_exporter_uptime_seconds"));
+    }
+
+    #[tokio::test]
+    /// Metrics should be served in the InfluxDB line protocol on a path of their own if asked to.
+    async fn metrics_in_influx_line_protocol() {
+        let endpoint = spawn_mock_api(include_str!("../tests/data/simple_one_monitor.json")).await;
+        let mut state = test_state_with_endpoint(&endpoint);
+        let req = Request::get("/influx").body(Body::empty()).unwrap();
+        let resp = hyper_service(req, &state).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        state.influx_path = Some("/influx".to_string());
+        let req = Request::get("/influx").body(Body::empty()).unwrap();
+        let resp = hyper_service(req, &state).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers()[header::CONTENT_TYPE],
+            "text/plain; charset=utf-8"
+        );
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = String::from_utf8_lossy(&body);
+        let line = body
+            .lines()
+            .find(|line| line.starts_with("site24x7_monitor_up,"))
+            .unwrap();
+        let (series, timestamp) = line.rsplit_once(' ').unwrap();
+        assert_eq!(
+            series,
+            r"site24x7_monitor_up,location=Bucharest\ -\ RO,monitor_id=01,monitor_name=test1,monitor_type=URL value=1"
+        );
+        assert!(timestamp.parse::<i64>().unwrap() > 0);
+    }
+
+    #[tokio::test]
+    /// Simultaneous scrapes should share a single upstream fetch.
+    async fn concurrent_scrapes_are_coalesced() {
+        let (endpoint, requests) = spawn_mock_api_with(
+            StatusCode::OK,
+            &[],
+            include_str!("../tests/data/simple_two_locations.json"),
+            Duration::from_millis(200),
+        )
+        .await;
+        let state = test_state_with_endpoint(&endpoint);
+        let (first, second) = tokio::join!(
+            hyper_service(
+                Request::get("/metrics").body(Body::empty()).unwrap(),
+                &state
+            ),
+            hyper_service(
+                Request::get("/metrics").body(Body::empty()).unwrap(),
+                &state
+            ),
+        );
+        assert_eq!(first.unwrap().status(), StatusCode::OK);
+        assert_eq!(second.unwrap().status(), StatusCode::OK);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        // Later scrapes should fetch fresh data again.
+        let req = Request::get("/metrics").body(Body::empty()).unwrap();
+        hyper_service(req, &state).await.unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    /// Scrapes within the cache TTL should reuse the previously fetched data.
+    async fn scrapes_within_cache_ttl_are_served_from_cache() {
+        let (endpoint, requests) = spawn_mock_api_with_status(
+            StatusCode::OK,
+            &[],
+            include_str!("../tests/data/simple_two_locations.json"),
+        )
+        .await;
+        let mut state = test_state_with_endpoint(&endpoint);
+        state.cache_ttl = Duration::from_secs(60);
+        for _ in 0..2 {
+            let req = Request::get("/metrics").body(Body::empty()).unwrap();
+            let resp = hyper_service(req, &state).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        // Once the data is too old, it should be fetched again.
+        state.cache_ttl = Duration::ZERO;
+        let req = Request::get("/metrics").body(Body::empty()).unwrap();
+        hyper_service(req, &state).await.unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    /// Failed fetches should be counted by their kind.
+    async fn scrape_errors_are_counted() {
+        let endpoint = spawn_mock_api(r#"{"data": {"monitors": [{"monitor_type": "URL"}]}}"#).await;
+        let state = test_state_with_endpoint(&endpoint);
+        let before = state
+            .metrics
+            .scrape_errors
+            .with_label_values(&["parse"])
+            .get();
+        let req = Request::get("/metrics").body(Body::empty()).unwrap();
+        hyper_service(req, &state).await.unwrap();
+        assert_eq!(
+            state
+                .metrics
+                .scrape_errors
+                .with_label_values(&["parse"])
+                .get(),
+            before + 1
+        );
+    }
+
+    #[tokio::test]
+    /// The field that broke parsing should be exposed until all accounts can be parsed again.
+    async fn parse_error_path_is_exposed() {
+        let endpoint = spawn_mock_api(include_str!("../tests/data/malformed_status.json")).await;
+        let mut state = test_state_with_endpoint(&endpoint);
+        let req = Request::get("/metrics").body(Body::empty()).unwrap();
+        hyper_service(req, &state).await.unwrap();
+        let metric_families = state.metrics.registry.gather();
+        let parse_error = metric_families
+            .iter()
+            .find(|mf| mf.get_name() == "site24x7_parse_error")
+            .unwrap();
+        assert_eq!(parse_error.get_metric().len(), 1);
+        assert_eq!(
+            parse_error.get_metric()[0].get_label()[0].get_value(),
+            "data.monitors[2].status"
+        );
+
+        let endpoint = spawn_mock_api(include_str!("../tests/data/simple_two_monitors.json")).await;
+        state.accounts = vec![test_account("", &endpoint)];
+        let req = Request::get("/metrics").body(Body::empty()).unwrap();
+        hyper_service(req, &state).await.unwrap();
+        let metric_families = state.metrics.registry.gather();
+        assert!(!metric_families
+            .iter()
+            .any(|mf| mf.get_name() == "site24x7_parse_error"));
+    }
+
+    #[tokio::test]
+    /// The process is healthy as long as it can answer.
+    async fn healthy_is_always_ok() {
+        let resp = request(Method::GET, HEALTHY_PATH).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    /// We're only ready once we got data from Site24x7.
+    async fn ready_after_first_successful_fetch() {
+        let endpoint =
+            spawn_mock_api(include_str!("../tests/data/simple_two_locations.json")).await;
+        let state = test_state_with_endpoint(&endpoint);
+
+        let req = Request::get(READY_PATH).body(Body::empty()).unwrap();
+        let resp = hyper_service(req, &state).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let req = Request::get("/metrics").body(Body::empty()).unwrap();
+        hyper_service(req, &state).await.unwrap();
+
+        let req = Request::get(READY_PATH).body(Body::empty()).unwrap();
+        let resp = hyper_service(req, &state).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    /// A successful scrape should be recorded as such.
+    async fn successful_scrape_is_recorded() {
+        let endpoint =
+            spawn_mock_api(include_str!("../tests/data/simple_two_locations.json")).await;
+        let state = test_state_with_endpoint(&endpoint);
+        let before = Utc::now().timestamp() as f64;
+        let req = Request::get("/metrics").body(Body::empty()).unwrap();
+        let resp = hyper_service(req, &state).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(state.metrics.last_scrape_success.get(), 1);
+        assert!(state.metrics.last_scrape_timestamp_seconds.get() >= before);
+    }
+
+    #[tokio::test]
+    /// A failed scrape should still serve the previously gathered metrics.
+    async fn failed_scrape_serves_previous_metrics() {
+        let endpoint =
+            spawn_mock_api(include_str!("../tests/data/simple_two_locations.json")).await;
+        let state = test_state_with_endpoint(&endpoint);
+        let req = Request::get("/metrics").body(Body::empty()).unwrap();
+        hyper_service(req, &state).await.unwrap();
+        let last_success = state.metrics.last_scrape_timestamp_seconds.get();
+
+        // Both states share their metrics.
+        let endpoint = spawn_mock_api("<html>Bad Gateway</html>").await;
+        let state = AppState {
+            metrics: state.metrics.clone(),
+            ..test_state_with_endpoint(&endpoint)
+        };
+        let req = Request::get("/metrics").body(Body::empty()).unwrap();
+        let resp = hyper_service(req, &state).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(state.metrics.last_scrape_success.get(), 0);
+        assert_eq!(
+            state
+                .metrics
+                .scrape_errors
+                .with_label_values(&["parse"])
+                .get(),
+            1
+        );
+        assert_eq!(
+            state.metrics.last_scrape_timestamp_seconds.get(),
+            last_success
+        );
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.contains("site24x7_monitor_up{"));
+        assert!(!body.contains("Bad Gateway"));
+    }
+
+    #[tokio::test]
+    /// A directly provided access token can't be renewed so we shouldn't even try.
+    async fn direct_access_token_is_not_renewed() {
+        let (endpoint, requests) =
+            spawn_mock_api_with_status(StatusCode::UNAUTHORIZED, &[], "{}").await;
+        let (zoho_endpoint, zoho_requests) =
+            spawn_mock_api_with_status(StatusCode::OK, &[], "{}").await;
+        let mut state = test_state_with_endpoint(&endpoint);
+        state.accounts[0].site24x7_client_info.zoho_endpoint = zoho_endpoint;
+        state.accounts[0].refresh_token = None;
+
+        let req = Request::get("/metrics").body(Body::empty()).unwrap();
+        let resp = hyper_service(req, &state).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        assert_eq!(zoho_requests.load(Ordering::SeqCst), 0);
+        assert_eq!(state.metrics.last_scrape_success.get(), 0);
+    }
+
+    #[tokio::test]
+    /// Renewing the access token should be counted and so should a failure to do so.
+    async fn failed_token_refresh_is_counted() {
+        let (endpoint, _) = spawn_mock_api_with_status(StatusCode::UNAUTHORIZED, &[], "{}").await;
+        let state = test_state_with_endpoint(&endpoint);
+
+        let req = Request::get("/metrics").body(Body::empty()).unwrap();
+        let resp = hyper_service(req, &state).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(state.metrics.token_refresh.get(), 1);
+        assert_eq!(state.metrics.token_refresh_failures.get(), 1);
+    }
+
+    #[tokio::test]
+    /// The uptime should be exposed and keep growing between scrapes.
+    async fn uptime_increases() {
+        let endpoint =
+            spawn_mock_api(include_str!("../tests/data/simple_two_locations.json")).await;
+        let state = test_state_with_endpoint(&endpoint);
+        let mut uptimes = vec![];
+        for _ in 0..2 {
+            let req = Request::get("/metrics").body(Body::empty()).unwrap();
+            let resp = hyper_service(req, &state).await.unwrap();
+            let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            let uptime = String::from_utf8_lossy(&body)
+                .lines()
+                .find_map(|l| l.strip_prefix("site24x7_exporter_uptime_seconds "))
+                .expect("uptime should be exposed")
+                .parse::<f64>()
+                .unwrap();
+            uptimes.push(uptime);
+        }
+        assert!(uptimes[0] > 0.0);
+        assert!(uptimes[1] > uptimes[0]);
+    }
+
+    #[tokio::test]
+    /// Geolocation info should be served compressed only if the client asks for it.
+    async fn geolocation_is_compressed_on_request() {
+        use std::io::Read;
+
+        let state = test_state();
+        let req = Request::get("/geolocation").body(Body::empty()).unwrap();
+        let resp = hyper_service(req, &state).await.unwrap();
+        assert!(resp.headers().get(header::CONTENT_ENCODING).is_none());
+        let plain = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        serde_json::from_slice::<serde_json::Value>(&plain).unwrap();
+
+        let req = Request::get("/geolocation")
+            .header(header::ACCEPT_ENCODING, "deflate, gzip;q=0.8")
+            .body(Body::empty())
+            .unwrap();
+        let resp = hyper_service(req, &state).await.unwrap();
+        assert_eq!(resp.headers()[header::CONTENT_ENCODING], "gzip");
+        let compressed = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let mut decompressed = vec![];
+        flate2::read::GzDecoder::new(&compressed[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, plain);
+    }
+
+    #[tokio::test]
+    /// Metrics should be compressed if the client accepts it just like Prometheus does.
+    async fn metrics_are_compressed_on_request() {
+        use std::io::Read;
+
+        let endpoint =
+            spawn_mock_api(include_str!("../tests/data/simple_two_locations.json")).await;
+        let state = test_state_with_endpoint(&endpoint);
+        let req = Request::get("/metrics")
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())
+            .unwrap();
+        let resp = hyper_service(req, &state).await.unwrap();
+        assert_eq!(resp.headers()[header::CONTENT_ENCODING], "gzip");
+        assert!(resp.headers()[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("text/plain"));
+        let compressed = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let mut decompressed = String::new();
+        flate2::read::GzDecoder::new(&compressed[..])
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert!(decompressed.contains("site24x7_monitor_up"));
+
+        let req = Request::get("/metrics").body(Body::empty()).unwrap();
+        let resp = hyper_service(req, &state).await.unwrap();
+        assert!(resp.headers().get(header::CONTENT_ENCODING).is_none());
+    }
+
+    #[tokio::test]
+    /// Every account should be fetched and its series labeled with its name.
+    async fn multiple_accounts() {
+        let first_endpoint =
+            spawn_mock_api(include_str!("../tests/data/simple_one_monitor.json")).await;
+        let second_endpoint =
+            spawn_mock_api(include_str!("../tests/data/simple_two_monitors.json")).await;
+        let mut state = test_state();
+        state.accounts = vec![
+            test_account("first", &first_endpoint),
+            test_account("second", &second_endpoint),
+        ];
+        state.metrics = Metrics::new(&crate::metrics::MetricsSettings {
+            include_account: true,
+            ..Default::default()
+        })
+        .unwrap();
+
+        for _ in 0..2 {
+            let req = Request::get("/metrics").body(Body::empty()).unwrap();
+            let resp = hyper_service(req, &state).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            let body = String::from_utf8(body.to_vec()).unwrap();
+            let up_series: Vec<_> = body
+                .lines()
+                .filter(|line| line.starts_with("site24x7_monitor_up{"))
+                .collect();
+            assert_eq!(up_series.len(), 3);
+            assert_eq!(
+                up_series
+                    .iter()
+                    .filter(|line| line.contains(r#"account="first""#))
+                    .count(),
+                1
+            );
+            assert_eq!(
+                up_series
+                    .iter()
+                    .filter(|line| line.contains(r#"account="second""#))
+                    .count(),
+                2
+            );
+            assert!(body.contains("site24x7_last_scrape_success 1"));
+        }
+    }
+
+    #[tokio::test]
+    /// An account that fails to be fetched should keep its series and its share of the totals.
+    async fn failed_account_keeps_last_metrics() {
+        let first_endpoint =
+            spawn_mock_api(include_str!("../tests/data/simple_one_monitor.json")).await;
+        let second_endpoint = spawn_mock_api(include_str!("../tests/data/group_status.json")).await;
+        let (failing_endpoint, _) =
+            spawn_mock_api_with_status(StatusCode::SERVICE_UNAVAILABLE, &[], "{}").await;
+        let mut state = test_state();
+        state.accounts = vec![
+            test_account("first", &first_endpoint),
+            test_account("second", &second_endpoint),
+        ];
+        state.metrics = Metrics::new(&crate::metrics::MetricsSettings {
+            include_account: true,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let mut bodies = vec![];
+        for _ in 0..2 {
+            let req = Request::get("/metrics").body(Body::empty()).unwrap();
+            let resp = hyper_service(req, &state).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            bodies.push(String::from_utf8(body.to_vec()).unwrap());
+            state.accounts[1].site24x7_client_info.site24x7_endpoint = failing_endpoint.clone();
+        }
+        assert!(bodies[1].contains("site24x7_last_scrape_success 0"));
+        for body in bodies {
+            assert!(body.contains(
+                r#"site24x7_monitor_group_up{account="second",monitor_group="production"} 1"#
+            ));
+            assert!(body.contains(r#"site24x7_monitors_total{monitor_type="RESTAPI"} 1"#));
+            assert!(body.contains(r#"site24x7_monitors_total{monitor_type="URL"} 2"#));
+            assert!(body.contains("site24x7_monitor_groups_total 3"));
+        }
+    }
+
+    #[tokio::test]
+    /// Bodies larger than allowed should be rejected before anything else.
+    async fn oversized_body_is_rejected() {
+        let state = test_state();
+        let req = Request::post("/metrics")
+            .body(Body::from(vec![b'x'; 1025]))
+            .unwrap();
+        let resp = hyper_service(req, &state).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let req = Request::post("/metrics")
+            .body(Body::from(vec![b'x'; 1024]))
+            .unwrap();
+        let resp = hyper_service(req, &state).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[tokio::test]
+    /// Bodies that don't declare their length should be rejected once they turn out too large.
+    async fn oversized_chunked_body_is_rejected() {
+        let state = test_state();
+        let chunked_body = |chunks: usize| {
+            Body::wrap_stream(futures_util::stream::iter(
+                (0..chunks).map(|_| Ok::<_, std::io::Error>(vec![b'x'; 512])),
+            ))
+        };
+        let req = Request::post("/metrics").body(chunked_body(3)).unwrap();
+        assert_eq!(req.body().size_hint().lower(), 0);
+        let resp = hyper_service(req, &state).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let req = Request::post("/metrics").body(chunked_body(2)).unwrap();
+        let resp = hyper_service(req, &state).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[tokio::test]
+    /// Metrics small enough to be buffered should tell their length, compressed or not.
+    async fn metrics_have_content_length() {
+        let endpoint =
+            spawn_mock_api(include_str!("../tests/data/simple_two_locations.json")).await;
+        let state = test_state_with_endpoint(&endpoint);
+        for accept_encoding in ["identity", "gzip"] {
+            let req = Request::get("/metrics")
+                .header(header::ACCEPT_ENCODING, accept_encoding)
+                .body(Body::empty())
+                .unwrap();
+            let resp = hyper_service(req, &state).await.unwrap();
+            let content_length = resp.headers()[header::CONTENT_LENGTH].clone();
+            let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            assert_eq!(content_length, body.len().to_string().as_str());
+        }
+    }
+
+    #[tokio::test]
+    /// Repeated scrapes should be able to reuse one connection.
+    async fn metrics_keep_alive() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let endpoint = spawn_mock_api(r#"{"data": {"monitors": []}}"#).await;
+        let state = Arc::new(test_state_with_endpoint(&endpoint));
+        let make_service = make_service_fn(move |_conn| {
+            let state = state.clone();
+            async move {
+                Ok::<_, hyper::Error>(service_fn(move |req| {
+                    let state = state.clone();
+                    async move { hyper_service(req, &state).await }
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        // The second request is only answered if the connection was kept open after the first.
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(
+                b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n\
+                GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert_eq!(response.matches("HTTP/1.1 200 OK").count(), 2);
+    }
+
+    #[tokio::test]
+    /// The allowed origin for geolocation info should be configurable and may be left out.
+    async fn geolocation_cors_allow_origin() {
+        let mut state = test_state();
+        let req = Request::get("/geolocation").body(Body::empty()).unwrap();
+        let resp = hyper_service(req, &state).await.unwrap();
+        assert_eq!(resp.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+
+        state.cors_allow_origin = "https://grafana.example.com".to_string();
+        let req = Request::get("/geolocation").body(Body::empty()).unwrap();
+        let resp = hyper_service(req, &state).await.unwrap();
+        assert_eq!(
+            resp.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://grafana.example.com"
+        );
+
+        state.cors_allow_origin = String::new();
+        let req = Request::get("/geolocation").body(Body::empty()).unwrap();
+        let resp = hyper_service(req, &state).await.unwrap();
+        assert!(resp
+            .headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .is_none());
+    }
+
+    #[test]
+    /// Every request should be logged along with its outcome.
+    fn requests_are_logged() {
+        assert_eq!(
+            access_log_line(
+                &Method::GET,
+                "/-/healthy",
+                StatusCode::OK,
+                Duration::from_millis(1234)
+            ),
+            "method=GET path=/-/healthy status=200 duration_seconds=1.234"
+        );
+        assert_eq!(
+            access_log_line(
+                &Method::POST,
+                "/metrics",
+                StatusCode::METHOD_NOT_ALLOWED,
+                Duration::ZERO
+            ),
+            "method=POST path=/metrics status=405 duration_seconds=0.000"
+        );
+    }
+
+    #[tokio::test]
+    /// Metrics should be served as delimited protobuf if the client asks for it.
+    async fn metrics_in_protobuf_format_on_request() {
+        let endpoint =
+            spawn_mock_api(include_str!("../tests/data/simple_two_locations.json")).await;
+        let state = test_state_with_endpoint(&endpoint);
+        let req = Request::get("/metrics")
+            .header(
+                header::ACCEPT,
+                "application/vnd.google.protobuf;proto=io.prometheus.client.MetricFamily;\
+                encoding=delimited;q=0.7,text/plain;version=0.0.4;q=0.3",
+            )
+            .body(Body::empty())
+            .unwrap();
+        let resp = hyper_service(req, &state).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers()[header::CONTENT_TYPE],
+            prometheus::PROTOBUF_FORMAT
+        );
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(!body.is_empty());
+        assert!(!String::from_utf8_lossy(&body).contains("# HELP"));
+    }
+
+    #[tokio::test]
+    /// Metrics should be served as OpenMetrics if the client prefers it like Prometheus does.
+    async fn metrics_in_openmetrics_format_on_request() {
+        let endpoint =
+            spawn_mock_api(include_str!("../tests/data/simple_two_locations.json")).await;
+        let state = test_state_with_endpoint(&endpoint);
+        let req = Request::get("/metrics")
+            .header(
+                header::ACCEPT,
+                "application/openmetrics-text;version=1.0.0,text/plain;version=0.0.4;q=0.5",
+            )
+            .body(Body::empty())
+            .unwrap();
+        let resp = hyper_service(req, &state).await.unwrap();
+        assert_eq!(resp.headers()[header::CONTENT_TYPE], OPENMETRICS_FORMAT);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).ends_with("# EOF\n"));
+
+        let req = Request::get("/metrics")
+            .header(header::ACCEPT, "text/plain")
+            .body(Body::empty())
+            .unwrap();
+        let resp = hyper_service(req, &state).await.unwrap();
+        assert!(resp.headers()[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("text/plain"));
+    }
+
+    #[tokio::test]
+    /// Geolocation info should be served as valid JSON.
+    async fn geolocation_is_valid_json() {
+        let resp = request(Method::GET, "/geolocation").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[header::CONTENT_TYPE], "application/json");
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let geolocation_info: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(!geolocation_info.as_array().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    /// Geolocation info should be served as GeoJSON if asked for by query or `Accept` header.
+    async fn geolocation_as_geojson() {
+        let state = test_state();
+        for req in [
+            Request::get("/geolocation?format=geojson"),
+            Request::get("/geolocation").header(header::ACCEPT, "application/geo+json"),
+        ] {
+            let resp = hyper_service(req.body(Body::empty()).unwrap(), &state)
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(resp.headers()[header::CONTENT_TYPE], "application/geo+json");
+            assert_eq!(
+                resp.headers()[header::ETAG],
+                state.geolocation_info.geojson.etag.as_str()
+            );
+            let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            let geojson: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(geojson["type"], "FeatureCollection");
+            assert!(!geojson["features"].as_array().unwrap().is_empty());
+        }
+    }
+
+    #[tokio::test]
+    /// Clients that already have the geolocation info should be told so instead of getting it
+    /// again.
+    async fn geolocation_is_cacheable() {
+        let state = test_state();
+        let req = Request::get("/geolocation").body(Body::empty()).unwrap();
+        let resp = hyper_service(req, &state).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers()[header::CACHE_CONTROL],
+            "public, max-age=86400"
+        );
+        let etag = resp.headers()[header::ETAG].clone();
+
+        let req = Request::get("/geolocation")
+            .header(header::IF_NONE_MATCH, etag.clone())
+            .body(Body::empty())
+            .unwrap();
+        let resp = hyper_service(req, &state).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(resp.headers()[header::ETAG], etag);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(body.is_empty());
+
+        let req = Request::get("/geolocation")
+            .header(header::IF_NONE_MATCH, "\"outdated\"")
+            .body(Body::empty())
+            .unwrap();
+        let resp = hyper_service(req, &state).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    /// Geolocation info shouldn't be exposed at all if disabled.
+    async fn geolocation_can_be_disabled() {
+        let mut state = test_state();
+        state.geolocation_path = None;
+        let req = Request::get("/geolocation").body(Body::empty()).unwrap();
+        let resp = hyper_service(req, &state).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    /// The raw API response should only be served if asked for as it might be sensitive.
+    async fn debug_path_is_disabled_by_default() {
+        let endpoint = spawn_mock_api(r#"{"data": {"monitors": []}}"#).await;
+        let mut state = test_state_with_endpoint(&endpoint);
+        let req = Request::get("/debug").body(Body::empty()).unwrap();
+        let resp = hyper_service(req, &state).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        state.debug_path = Some("/debug".to_string());
+        let req = Request::get("/debug").body(Body::empty()).unwrap();
+        let resp = hyper_service(req, &state).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(body, "{\n  \"data\": {\n    \"monitors\": []\n  }\n}");
+    }
+
+    #[tokio::test]
+    /// The raw API response should be fetched with a renewed access token once the current one
+    /// has expired.
+    async fn debug_path_renews_access_token() {
+        let make_service = make_service_fn(|_conn| async {
+            Ok::<_, hyper::Error>(service_fn(|req: Request<Body>| async move {
+                let renewed = req.headers()[header::AUTHORIZATION] == "Zoho-oauthtoken renewed";
+                Response::builder()
+                    .status(if renewed {
+                        StatusCode::OK
+                    } else {
+                        StatusCode::UNAUTHORIZED
+                    })
+                    .body(Body::from(r#"{"data": {"monitors": []}}"#))
+            }))
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let endpoint = format!("http://{}/api", server.local_addr());
+        tokio::spawn(server);
+        let zoho_endpoint = spawn_mock_api(
+            r#"{"access_token": "renewed", "expires_in": 3600,
+                "api_domain": "https://www.zohoapis.com", "token_type": "Bearer"}"#,
+        )
+        .await;
+        let mut state = test_state_with_endpoint(&endpoint);
+        state.accounts[0].site24x7_client_info.zoho_endpoint = zoho_endpoint;
+        state.debug_path = Some("/debug".to_string());
+
+        let req = Request::get("/debug").body(Body::empty()).unwrap();
+        let resp = hyper_service(req, &state).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(*state.accounts[0].access_token.read().await, "renewed");
+        assert_eq!(state.metrics.token_refresh.get(), 1);
+    }
+
+    #[tokio::test]
+    /// A response that can't be built should be an error rather than a crash.
+    async fn invalid_response_is_internal_server_error() {
+        let mut state = test_state();
+        state.cors_allow_origin = "https://example.com\n".to_string();
+        let req = Request::get("/geolocation").body(Body::empty()).unwrap();
+        let resp = hyper_service(req, &state).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[test]
+    /// Every kind of error should be answered with its own status code.
+    fn web_error_status_codes() {
+        let cases = [
+            (
+                WebError::Internal(anyhow::anyhow!("oops")),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (
+                WebError::Upstream(site24x7_types::CurrentStatusError::ApiTimeoutError(
+                    "slow".to_string(),
+                )),
+                StatusCode::BAD_GATEWAY,
+            ),
+            (WebError::NotReady, StatusCode::SERVICE_UNAVAILABLE),
+            (WebError::PayloadTooLarge(1), StatusCode::PAYLOAD_TOO_LARGE),
+        ];
+        for (e, status) in cases {
+            assert_eq!(e.status(), status);
+            assert_eq!(Response::from(e).status(), status);
+        }
+    }
+
+    #[tokio::test]
+    /// Failing to fetch the raw API response is Site24x7's fault rather than ours.
+    async fn debug_path_upstream_error_is_bad_gateway() {
+        let (endpoint, _) =
+            spawn_mock_api_with_status(StatusCode::SERVICE_UNAVAILABLE, &[], "{}").await;
+        let mut state = test_state_with_endpoint(&endpoint);
+        state.debug_path = Some("/debug".to_string());
+        let req = Request::get("/debug").body(Body::empty()).unwrap();
+        let resp = hyper_service(req, &state).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+    }
+
+    #[test]
+    /// Clients may explicitly refuse gzip.
+    fn gzip_refused_with_zero_quality() {
+        let req = Request::get("/")
+            .header(header::ACCEPT_ENCODING, "gzip;q=0")
+            .body(Body::empty())
+            .unwrap();
+        assert!(!accepts_gzip(&req));
+    }
 }
@@ -1,5 +1,8 @@
 //! Module containing Zoho API-specific types.
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use std::time::Duration;
 
 #[derive(Serialize, Debug)]
 pub struct AccessTokenRequest {
@@ -12,9 +15,7 @@ pub struct AccessTokenRequest {
 #[derive(Deserialize, Debug)]
 pub struct AccessTokenResponseInner {
     pub access_token: String,
-    #[allow(dead_code)]
     pub expires_in: u32,
-    #[allow(dead_code)]
     pub api_domain: String,
     #[allow(dead_code)]
     pub token_type: String,
@@ -31,3 +32,22 @@ pub enum AccessTokenResponse {
 pub struct ApiError {
     pub error: String,
 }
+
+/// An access token along with how long it is valid for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AccessToken {
+    pub access_token: String,
+    /// Time from when the token was issued until it expires.
+    pub expires_in: Duration,
+    /// The Zoho API domain that issued the token.
+    pub api_domain: String,
+}
+
+#[derive(Error, Debug)]
+pub enum AccessTokenError {
+    #[error("API request timed out: {0}")]
+    ApiTimeoutError(String),
+
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
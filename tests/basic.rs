@@ -1,6 +1,7 @@
 use assert_cmd::prelude::*;
 use clap::{crate_name, crate_version};
 use std::process::Command;
+use std::time::Duration;
 
 /// Error type used by tests
 pub type Error = Box<dyn std::error::Error>;
@@ -16,6 +17,371 @@ fn help_shows() -> Result<(), Error> {
     Ok(())
 }
 
+/// All missing secrets should be reported together.
+#[test]
+fn missing_secrets_are_reported_together() -> Result<(), Error> {
+    let output = Command::cargo_bin("site24x7_exporter")?
+        .env_remove("ZOHO_ACCESS_TOKEN")
+        .env_remove("ZOHO_CLIENT_ID")
+        .env_remove("ZOHO_CLIENT_SECRET")
+        .env_remove("ZOHO_REFRESH_TOKEN")
+        .current_dir(std::env::temp_dir())
+        .output()?;
+    assert!(!output.status.success());
+
+    let stderr = String::from_utf8(output.stderr)?;
+    for env_var in ["ZOHO_CLIENT_ID", "ZOHO_CLIENT_SECRET", "ZOHO_REFRESH_TOKEN"] {
+        assert!(
+            stderr.contains(env_var),
+            "{} missing in: {}",
+            env_var,
+            stderr
+        );
+    }
+
+    Ok(())
+}
+
+/// Variables from a custom env file should be picked up.
+#[test]
+fn env_file_is_loaded() -> Result<(), Error> {
+    let env_file =
+        std::env::temp_dir().join(format!("site24x7_exporter_test_{}.env", std::process::id()));
+    std::fs::write(
+        &env_file,
+        "ZOHO_CLIENT_ID=env-file-client-id\nZOHO_CLIENT_SECRET=env-file-client-secret\n",
+    )?;
+
+    let output = Command::cargo_bin("site24x7_exporter")?
+        .arg("--env-file")
+        .arg(&env_file)
+        .env_remove("ZOHO_ACCESS_TOKEN")
+        .env_remove("ZOHO_CLIENT_ID")
+        .env_remove("ZOHO_CLIENT_SECRET")
+        .env_remove("ZOHO_REFRESH_TOKEN")
+        .current_dir(std::env::temp_dir())
+        .output();
+    std::fs::remove_file(&env_file)?;
+    let output = output?;
+    assert!(!output.status.success());
+
+    // Only the variable missing from the env file should be reported.
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(stderr.contains("ZOHO_REFRESH_TOKEN"));
+    assert!(!stderr.contains("ZOHO_CLIENT_ID"));
+    assert!(!stderr.contains("ZOHO_CLIENT_SECRET"));
+
+    Ok(())
+}
+
+/// Log messages should be JSON objects when asked for.
+#[test]
+fn log_format_json() -> Result<(), Error> {
+    let output = Command::cargo_bin("site24x7_exporter")?
+        .args(["--log.format", "json"])
+        // Make sure we fail right after logging our version as the secrets are missing.
+        .env_remove("ZOHO_CLIENT_ID")
+        .current_dir(std::env::temp_dir())
+        .output()?;
+    assert!(!output.status.success());
+
+    let stdout = String::from_utf8(output.stdout)?;
+    let first_line = stdout.lines().next().ok_or("no log output")?;
+    let log_line: serde_json::Value = serde_json::from_str(first_line)?;
+    assert_eq!(log_line["level"], "INFO");
+    assert_eq!(
+        log_line["message"],
+        format!("{} {}", crate_name!(), crate_version!())
+    );
+    assert!(log_line["timestamp"].is_string());
+    assert!(log_line["module"].is_string());
+
+    Ok(())
+}
+
+/// An invalid proxy should be reported before anything else happens.
+#[test]
+fn invalid_proxy_fails_at_startup() -> Result<(), Error> {
+    let output = Command::cargo_bin("site24x7_exporter")?
+        .args(["--api.proxy", "ftp://proxy.example.com"])
+        .current_dir(std::env::temp_dir())
+        .output()?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)?.contains("--api.proxy 'ftp://proxy.example.com"));
+
+    Ok(())
+}
+
+/// A TLS client certificate is of no use without its key.
+#[test]
+fn client_cert_without_key_fails_at_startup() -> Result<(), Error> {
+    let output = Command::cargo_bin("site24x7_exporter")?
+        .args(["--api.client-cert", "client.pem"])
+        .current_dir(std::env::temp_dir())
+        .output()?;
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8(output.stderr)?.contains("--api.client-cert requires --api.client-key")
+    );
+
+    Ok(())
+}
+
+/// Paths that would shadow each other should be reported before anything else happens.
+#[test]
+fn colliding_paths_fail_at_startup() -> Result<(), Error> {
+    let output = Command::cargo_bin("site24x7_exporter")?
+        .args(["--web.telemetry-path", "/site24x7"])
+        .args(["--web.geolocation-path", "/site24x7"])
+        .current_dir(std::env::temp_dir())
+        .output()?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)?
+        .contains("--web.geolocation-path '/site24x7': already used by --web.telemetry-path"));
+
+    Ok(())
+}
+
+/// A directly provided access token should be used without requesting one from Zoho.
+#[test]
+fn direct_access_token_skips_token_request() -> Result<(), Error> {
+    let port = port_check::free_local_port().ok_or("no free port")?;
+    let mut child = Command::cargo_bin("site24x7_exporter")?
+        .args(["--web.listen-address", &format!("127.0.0.1:{port}")])
+        // Requesting a token from here would fail and make us exit.
+        .args(["--zoho.accounts-url", "http://127.0.0.1:1"])
+        .env("ZOHO_ACCESS_TOKEN", "access-token")
+        .env_remove("ZOHO_CLIENT_ID")
+        .env_remove("ZOHO_CLIENT_SECRET")
+        .env_remove("ZOHO_REFRESH_TOKEN")
+        .current_dir(std::env::temp_dir())
+        .spawn()?;
+
+    // Wait for the server to come up.
+    let reachable = (0..100).any(|_| {
+        std::thread::sleep(Duration::from_millis(100));
+        port_check::is_port_reachable(("127.0.0.1", port))
+    });
+    let still_running = child.try_wait()?.is_none();
+    child.kill()?;
+    child.wait()?;
+    assert!(reachable);
+    assert!(still_running);
+
+    Ok(())
+}
+
+/// Metrics should be served on every given listen address.
+#[test]
+fn multiple_listen_addresses() -> Result<(), Error> {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    let first_port = port_check::free_local_port().ok_or("no free port")?;
+    let second_port = (0..100)
+        .filter_map(|_| port_check::free_local_port())
+        .find(|&port| port != first_port)
+        .ok_or("no second free port")?;
+    let ports = [first_port, second_port];
+    let mut child = Command::cargo_bin("site24x7_exporter")?
+        .args(["--web.listen-address", &format!("127.0.0.1:{}", ports[0])])
+        .args(["--web.listen-address", &format!("127.0.0.1:{}", ports[1])])
+        // Fetching fails right away but we still serve the metrics about that.
+        .args(["--site24x7.endpoint-url", "http://127.0.0.1:1"])
+        .env("ZOHO_ACCESS_TOKEN", "access-token")
+        .current_dir(std::env::temp_dir())
+        .spawn()?;
+
+    let responses: Vec<_> = ports
+        .iter()
+        .map(|port| {
+            // Wait for the server to come up.
+            let stream = (0..100).find_map(|_| {
+                std::thread::sleep(Duration::from_millis(100));
+                TcpStream::connect(("127.0.0.1", *port)).ok()
+            });
+            stream.map(|mut stream| -> Result<String, Error> {
+                stream.write_all(
+                    b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+                )?;
+                let mut response = String::new();
+                stream.read_to_string(&mut response)?;
+                Ok(response)
+            })
+        })
+        .collect();
+    child.kill()?;
+    child.wait()?;
+
+    for response in responses {
+        let response = response.ok_or("port never became connectable")??;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("site24x7_last_scrape_success"));
+    }
+
+    Ok(())
+}
+
+/// We should exit cleanly once the given number of scrapes has been answered.
+#[test]
+fn exit_after_requests() -> Result<(), Error> {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    let port = port_check::free_local_port().ok_or("no free port")?;
+    let mut child = Command::cargo_bin("site24x7_exporter")?
+        .args(["--web.listen-address", &format!("127.0.0.1:{port}")])
+        .args(["--exit-after-requests", "2"])
+        // Fetching fails right away but we still serve the metrics about that.
+        .args(["--site24x7.endpoint-url", "http://127.0.0.1:1"])
+        .env("ZOHO_ACCESS_TOKEN", "access-token")
+        .current_dir(std::env::temp_dir())
+        .spawn()?;
+
+    let scrape = || -> Result<String, Error> {
+        // Wait for the server to come up.
+        let mut stream = (0..100)
+            .find_map(|_| {
+                std::thread::sleep(Duration::from_millis(100));
+                TcpStream::connect(("127.0.0.1", port)).ok()
+            })
+            .ok_or("port never became connectable")?;
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        Ok(response)
+    };
+    let responses = [scrape(), scrape()];
+
+    let exited = (0..100).find_map(|_| {
+        std::thread::sleep(Duration::from_millis(100));
+        child.try_wait().transpose()
+    });
+    if exited.is_none() {
+        child.kill()?;
+        child.wait()?;
+    }
+    for response in responses {
+        assert!(response?.starts_with("HTTP/1.1 200 OK"));
+    }
+    assert!(exited.ok_or("didn't exit")??.success());
+
+    Ok(())
+}
+
+/// Metrics should be served over a Unix domain socket instead of TCP when asked to.
+#[cfg(target_os = "linux")]
+#[test]
+fn metrics_over_unix_socket() -> Result<(), Error> {
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+
+    let socket_path = std::env::temp_dir().join(format!(
+        "site24x7_exporter-test-{}.sock",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&socket_path);
+    let mut child = Command::cargo_bin("site24x7_exporter")?
+        .arg("--web.unix-socket")
+        .arg(&socket_path)
+        // Fetching fails right away but we still serve the metrics about that.
+        .args(["--site24x7.endpoint-url", "http://127.0.0.1:1"])
+        .env("ZOHO_ACCESS_TOKEN", "access-token")
+        .current_dir(std::env::temp_dir())
+        .spawn()?;
+
+    // Wait for the server to come up.
+    let stream = (0..100).find_map(|_| {
+        std::thread::sleep(Duration::from_millis(100));
+        UnixStream::connect(&socket_path).ok()
+    });
+    let response = stream.map(|mut stream| -> Result<String, Error> {
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        Ok(response)
+    });
+    child.kill()?;
+    child.wait()?;
+    std::fs::remove_file(&socket_path)?;
+
+    let response = response.ok_or("socket never became connectable")??;
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    assert!(response.contains("site24x7_last_scrape_success 0"));
+
+    Ok(())
+}
+
+/// Checking credentials that Site24x7 rejects should fail instead of starting the server.
+#[test]
+fn check_with_bad_credentials_fails() -> Result<(), Error> {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let endpoint = format!("http://{}", listener.local_addr()?);
+    std::thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let _ = stream.read(&mut [0; 4096]);
+            let _ = stream.write_all(
+                b"HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            );
+        }
+    });
+
+    let output = Command::cargo_bin("site24x7_exporter")?
+        .arg("--check")
+        .args(["--site24x7.endpoint-url", &endpoint])
+        .env("ZOHO_ACCESS_TOKEN", "bad-access-token")
+        .current_dir(std::env::temp_dir())
+        .output()?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)?.contains("Couldn't fetch the current status"));
+
+    Ok(())
+}
+
+/// Upstream requests should identify us by name and version.
+#[test]
+fn default_user_agent_includes_version() -> Result<(), Error> {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let endpoint = format!("http://{}", listener.local_addr()?);
+    let server = std::thread::spawn(move || -> std::io::Result<String> {
+        let (mut stream, _) = listener.accept()?;
+        let mut request = vec![0; 4096];
+        let len = stream.read(&mut request)?;
+        let body = r#"{"data": {"monitors": []}}"#;
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )?;
+        Ok(String::from_utf8_lossy(&request[..len]).into_owned())
+    });
+
+    Command::cargo_bin("site24x7_exporter")?
+        .arg("--check")
+        .args(["--site24x7.endpoint-url", &endpoint])
+        .env("ZOHO_ACCESS_TOKEN", "access-token")
+        .current_dir(std::env::temp_dir())
+        .assert()
+        .success();
+
+    let request = server.join().unwrap()?.to_lowercase();
+    assert!(request.contains(&format!(
+        "user-agent: {}/{}\r\n",
+        crate_name!(),
+        crate_version!()
+    )));
+
+    Ok(())
+}
+
 /// Show version and exit.
 #[test]
 fn version_shows() -> Result<(), Error> {